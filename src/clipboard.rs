@@ -0,0 +1,79 @@
+//! Copying to and pasting from the Wayland clipboard, through `wl-copy` and
+//! `wl-paste`.
+
+use anyhow::{anyhow, Context, Result};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// Puts `data` on the clipboard as `mime_type`. `wl-copy` reads it all,
+/// then keeps serving it in the background until something else is copied.
+pub fn copy(data: &[u8], mime_type: &str) -> Result<()> {
+    let mut child = Command::new("wl-copy")
+        .args(["--type", mime_type])
+        .stdin(Stdio::piped())
+        // The background process inherits these, so nothing may wait on them
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .context("Failed to run wl-copy (is wl-clipboard installed?)")?;
+
+    let written = child
+        .stdin
+        .take()
+        .map(|mut stdin| stdin.write_all(data))
+        .unwrap_or(Ok(()));
+    let status = child.wait().context("Failed to run wl-copy")?;
+    written.context("Failed to pass the data to wl-copy")?;
+    if !status.success() {
+        return Err(anyhow!("wl-copy failed ({})", status));
+    }
+    Ok(())
+}
+
+/// Reads the image on the clipboard, preferring PNG when it is offered in
+/// several formats.
+pub fn paste_image() -> Result<Vec<u8>> {
+    let types = wl_paste(&["--list-types"])?;
+    let types = String::from_utf8_lossy(&types);
+    let mime_type = types
+        .lines()
+        .find(|mime_type| *mime_type == "image/png")
+        .or_else(|| {
+            types
+                .lines()
+                .find(|mime_type| mime_type.starts_with("image/"))
+        })
+        .ok_or_else(|| anyhow!("There is no image on the clipboard"))?;
+    wl_paste(&["--no-newline", "--type", mime_type])
+}
+
+fn wl_paste(args: &[&str]) -> Result<Vec<u8>> {
+    let output = Command::new("wl-paste")
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .context("Failed to run wl-paste (is wl-clipboard installed?)")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "wl-paste failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// A `file://` URI for an absolute path, with anything but unreserved
+/// characters and slashes percent-encoded.
+pub fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for byte in path.as_os_str().as_encoded_bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(*byte as char)
+            }
+            _ => uri.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    uri
+}
@@ -0,0 +1,239 @@
+//! Color management: converts images from their embedded ICC profile (or
+//! sRGB when they have none) to the display's profile, using lcms2 when the
+//! `icc` feature is enabled.
+
+use anyhow::Result;
+use image::DynamicImage;
+use std::path::Path;
+
+use crate::config::{expand_path, ColorSettings};
+use crate::source::ImageSource;
+
+pub struct ColorManager {
+    /// The display's ICC profile, or `None` for an sRGB display
+    #[cfg_attr(not(feature = "icc"), allow(dead_code))]
+    display_profile: Option<Vec<u8>>,
+    /// Off shows the raw pixel values, to compare against managed rendering
+    pub enabled: bool,
+}
+
+impl ColorManager {
+    /// Loads the configured display profile.
+    pub fn new(settings: &ColorSettings) -> Result<Self> {
+        let display_profile = if settings.display_profile.is_empty() {
+            None
+        } else {
+            Some(load_display_profile(&expand_path(
+                &settings.display_profile,
+            ))?)
+        };
+
+        Ok(Self {
+            display_profile,
+            enabled: settings.managed && Self::is_available(),
+        })
+    }
+
+    /// Whether this build can do color management at all.
+    pub fn is_available() -> bool {
+        cfg!(feature = "icc")
+    }
+
+    /// Converts a freshly decoded image to the display's color space, given
+    /// the file and page it was decoded from. Images without a profile are
+    /// taken to be sRGB, and float images, which are linear light rather
+    /// than profiled, are left alone.
+    pub fn convert(
+        &self,
+        img: &mut DynamicImage,
+        data: &[u8],
+        source: &ImageSource,
+        page: usize,
+    ) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        convert(img, data, source, page, self.display_profile.as_deref())
+    }
+}
+
+impl Default for ColorManager {
+    fn default() -> Self {
+        Self {
+            display_profile: None,
+            enabled: Self::is_available(),
+        }
+    }
+}
+
+#[cfg(feature = "icc")]
+fn load_display_profile(path: &Path) -> Result<Vec<u8>> {
+    use anyhow::Context;
+
+    let data = std::fs::read(path)
+        .with_context(|| format!("Failed to read display profile {}", path.display()))?;
+    lcms2::Profile::new_icc(&data)
+        .with_context(|| format!("Invalid display profile {}", path.display()))?;
+    Ok(data)
+}
+
+#[cfg(not(feature = "icc"))]
+fn load_display_profile(path: &Path) -> Result<Vec<u8>> {
+    Err(anyhow::anyhow!(
+        "Cannot use display profile {}: color management is not enabled in this build (rebuild with `--features icc`)",
+        path.display()
+    ))
+}
+
+#[cfg(feature = "icc")]
+fn convert(
+    img: &mut DynamicImage,
+    data: &[u8],
+    source: &ImageSource,
+    page: usize,
+    display_profile: Option<&[u8]>,
+) -> Result<()> {
+    use anyhow::Context;
+    use lcms2::{ColorSpaceSignature, Flags, Intent, PixelFormat, Profile, Transform};
+
+    let embedded = embedded_profile(data, source, page);
+    if embedded.is_none() && display_profile.is_none() {
+        // sRGB to sRGB
+        return Ok(());
+    }
+
+    let input = match &embedded {
+        Some(data) => Profile::new_icc(data).context("Invalid embedded ICC profile")?,
+        None => Profile::new_srgb(),
+    };
+    let output = match display_profile {
+        Some(data) => Profile::new_icc(data).context("Invalid display profile")?,
+        None => Profile::new_srgb(),
+    };
+
+    let gray = match input.color_space() {
+        ColorSpaceSignature::RgbData => false,
+        ColorSpaceSignature::GrayData => true,
+        // The decoder has already turned CMYK and friends into RGB, so
+        // their profiles no longer describe the pixels
+        _ => return Ok(()),
+    };
+
+    let intent = Intent::Perceptual;
+    let converted = match img {
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => return Ok(()),
+        DynamicImage::ImageLuma16(_)
+        | DynamicImage::ImageLumaA16(_)
+        | DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_) => {
+            let mut rgba = img.to_rgba16();
+            if gray {
+                let luma = img.to_luma_alpha16();
+                let transform = Transform::<[u16; 2], [u16; 4]>::new_flags(
+                    &input,
+                    PixelFormat::GRAYA_16,
+                    &output,
+                    PixelFormat::RGBA_16,
+                    intent,
+                    Flags::COPY_ALPHA,
+                )?;
+                transform.transform_pixels(pixels(&luma), pixels_mut(&mut rgba));
+            } else {
+                let transform = Transform::<[u16; 4], [u16; 4]>::new_flags(
+                    &input,
+                    PixelFormat::RGBA_16,
+                    &output,
+                    PixelFormat::RGBA_16,
+                    intent,
+                    Flags::COPY_ALPHA,
+                )?;
+                transform.transform_in_place(pixels_mut(&mut rgba));
+            }
+            DynamicImage::ImageRgba16(rgba)
+        }
+        _ => {
+            let mut rgba = img.to_rgba8();
+            if gray {
+                let luma = img.to_luma_alpha8();
+                let transform = Transform::<[u8; 2], [u8; 4]>::new_flags(
+                    &input,
+                    PixelFormat::GRAYA_8,
+                    &output,
+                    PixelFormat::RGBA_8,
+                    intent,
+                    Flags::COPY_ALPHA,
+                )?;
+                transform.transform_pixels(pixels(&luma), pixels_mut(&mut rgba));
+            } else {
+                let transform = Transform::<[u8; 4], [u8; 4]>::new_flags(
+                    &input,
+                    PixelFormat::RGBA_8,
+                    &output,
+                    PixelFormat::RGBA_8,
+                    intent,
+                    Flags::COPY_ALPHA,
+                )?;
+                transform.transform_in_place(pixels_mut(&mut rgba));
+            }
+            DynamicImage::ImageRgba8(rgba)
+        }
+    };
+
+    *img = converted;
+    Ok(())
+}
+
+#[cfg(not(feature = "icc"))]
+fn convert(
+    _img: &mut DynamicImage,
+    _data: &[u8],
+    _source: &ImageSource,
+    _page: usize,
+    _display_profile: Option<&[u8]>,
+) -> Result<()> {
+    Ok(())
+}
+
+/// Views an image buffer's samples as whole pixels.
+#[cfg(feature = "icc")]
+fn pixels<P, const N: usize>(
+    buffer: &image::ImageBuffer<P, Vec<P::Subpixel>>,
+) -> &[[P::Subpixel; N]]
+where
+    P: image::Pixel,
+    P::Subpixel: bytemuck::Pod,
+{
+    bytemuck::cast_slice(buffer.as_raw())
+}
+
+#[cfg(feature = "icc")]
+fn pixels_mut<P, const N: usize>(
+    buffer: &mut image::ImageBuffer<P, Vec<P::Subpixel>>,
+) -> &mut [[P::Subpixel; N]]
+where
+    P: image::Pixel,
+    P::Subpixel: bytemuck::Pod,
+{
+    bytemuck::cast_slice_mut(buffer)
+}
+
+/// Reads the ICC profile embedded in an image, for the formats that carry
+/// one. TIFF pages and the images of a HEIF collection each have their own.
+#[cfg(feature = "icc")]
+fn embedded_profile(data: &[u8], source: &ImageSource, page: usize) -> Option<Vec<u8>> {
+    use crate::formats::{detect_source_format, Format};
+    use image::codecs::{jpeg::JpegDecoder, png::PngDecoder, webp::WebPDecoder};
+    use image::{ImageDecoder, ImageFormat};
+    use std::io::Cursor;
+
+    let reader = || Cursor::new(data);
+    match detect_source_format(data, source)? {
+        Format::Image(ImageFormat::Png) => PngDecoder::new(reader()).ok()?.icc_profile(),
+        Format::Image(ImageFormat::Jpeg) => JpegDecoder::new(reader()).ok()?.icc_profile(),
+        Format::Image(ImageFormat::WebP) => WebPDecoder::new(reader()).ok()?.icc_profile(),
+        Format::Image(ImageFormat::Tiff) => crate::pages::tiff_icc_profile(data, page),
+        #[cfg(feature = "heif")]
+        Format::Heif | Format::Avif => crate::heif::icc_profile(data, page),
+        _ => None,
+    }
+}
@@ -0,0 +1,250 @@
+//! External commands from `[[commands]]` in the config, such as opening the
+//! current image in an editor or passing the marked ones to an upload
+//! script.
+
+use anyhow::{anyhow, Context, Result};
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+
+use crate::config::CommandSettings;
+
+/// A command that is being waited for.
+pub struct Running {
+    pub name: String,
+    /// The images to show again once it has finished, if any
+    pub reload: Vec<PathBuf>,
+    result: mpsc::Receiver<Result<()>>,
+}
+
+impl Running {
+    /// How the command went, once it has finished.
+    pub fn finished(&self) -> Option<Result<()>> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Err(anyhow!("stopped unexpectedly"))),
+        }
+    }
+}
+
+/// Starts a command for the current image and the marked ones (or just the
+/// current one when nothing is marked). Returns the command to wait for, or
+/// `None` if it was left to run on its own.
+pub fn run(
+    command: &CommandSettings,
+    current: &Path,
+    marked: &[PathBuf],
+) -> Result<Option<Running>> {
+    let mut process = Command::new("sh");
+    process
+        .arg("-c")
+        .arg(expand(&command.command, current, marked))
+        .stdin(Stdio::null())
+        .stdout(Stdio::null());
+
+    if !command.wait && !command.reload {
+        let mut child = process
+            .stderr(Stdio::null())
+            // Its own process group, so it outlives the terminal EleViewr
+            // was started from
+            .process_group(0)
+            .spawn()
+            .with_context(|| format!("Failed to run {}", command.name))?;
+        // Reap it when it exits, without holding anything up
+        std::thread::spawn(move || child.wait());
+        return Ok(None);
+    }
+
+    let child = process
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", command.name))?;
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let result = child
+            .wait_with_output()
+            .map_err(anyhow::Error::from)
+            .and_then(|output| {
+                if output.status.success() {
+                    return Ok(());
+                }
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                match stderr.trim().lines().last() {
+                    Some(line) => Err(anyhow!("{} ({})", line, output.status)),
+                    None => Err(anyhow!("{}", output.status)),
+                }
+            });
+        let _ = sender.send(result);
+    });
+
+    Ok(Some(Running {
+        name: command.name.clone(),
+        reload: if command.reload {
+            marked.to_vec()
+        } else {
+            Vec::new()
+        },
+        result: receiver,
+    }))
+}
+
+/// Fills in a command template, quoting each path for the shell: `{path}`
+/// is the current image, `{dir}` its folder, `{name}` its file name and
+/// `{marked}` every marked image. Other braces are left alone, so shell
+/// syntax such as `${HOME}` still works.
+fn expand(template: &str, current: &Path, marked: &[PathBuf]) -> OsString {
+    let mut expanded = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.extend_from_slice(&rest.as_bytes()[..start]);
+        rest = &rest[start..];
+
+        let Some(placeholder) = ["{path}", "{dir}", "{name}", "{marked}"]
+            .into_iter()
+            .find(|placeholder| rest.starts_with(placeholder))
+        else {
+            expanded.push(b'{');
+            rest = &rest[1..];
+            continue;
+        };
+        rest = &rest[placeholder.len()..];
+
+        match placeholder {
+            "{path}" => quote(&mut expanded, current),
+            "{dir}" => {
+                let dir = current
+                    .parent()
+                    .filter(|dir| !dir.as_os_str().is_empty())
+                    .unwrap_or(Path::new("."));
+                quote(&mut expanded, dir);
+            }
+            "{name}" => quote(
+                &mut expanded,
+                Path::new(current.file_name().unwrap_or_default()),
+            ),
+            _ => {
+                for (i, path) in marked.iter().enumerate() {
+                    if i > 0 {
+                        expanded.push(b' ');
+                    }
+                    quote(&mut expanded, path);
+                }
+            }
+        }
+    }
+    expanded.extend_from_slice(rest.as_bytes());
+    OsString::from_vec(expanded)
+}
+
+/// Appends a path in single quotes, which the shell takes literally.
+fn quote(expanded: &mut Vec<u8>, path: &Path) {
+    expanded.push(b'\'');
+    for byte in path.as_os_str().as_encoded_bytes() {
+        match byte {
+            b'\'' => expanded.extend_from_slice(b"'\\''"),
+            byte => expanded.push(*byte),
+        }
+    }
+    expanded.push(b'\'');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Names the shell must not split, expand or run.
+    const AWKWARD: [&str; 6] = [
+        "/pics/with space.png",
+        "/pics/it's.png",
+        "/pics/$(echo oops).png",
+        "/pics/`id`.png",
+        "/pics/line\nbreak.png",
+        "/pics/-rf.png",
+    ];
+
+    fn expand_str(template: &str, current: &str, marked: &[&str]) -> String {
+        let marked: Vec<PathBuf> = marked.iter().map(PathBuf::from).collect();
+        expand(template, Path::new(current), &marked)
+            .into_string()
+            .unwrap()
+    }
+
+    /// Runs `printf` on the expanded arguments, returning what the shell
+    /// passed it.
+    fn shell_words(template: &str, current: &str, marked: &[&str]) -> Vec<String> {
+        let marked: Vec<PathBuf> = marked.iter().map(PathBuf::from).collect();
+        let mut script = OsString::from("printf '%s\\0' ");
+        script.push(expand(template, Path::new(current), &marked));
+        let output = Command::new("sh").arg("-c").arg(script).output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .split_terminator('\0')
+            .map(str::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn placeholders() {
+        assert_eq!(
+            expand_str("gimp {path}", "/pics/a.png", &[]),
+            "gimp '/pics/a.png'"
+        );
+        assert_eq!(expand_str("cd {dir}", "/pics/a.png", &[]), "cd '/pics'");
+        assert_eq!(expand_str("cd {dir}", "a.png", &[]), "cd '.'");
+        assert_eq!(
+            expand_str("echo {name}", "/pics/a.png", &[]),
+            "echo 'a.png'"
+        );
+        assert_eq!(
+            expand_str(
+                "up {marked}",
+                "/pics/a.png",
+                &["/pics/a.png", "/pics/b.png"]
+            ),
+            "up '/pics/a.png' '/pics/b.png'"
+        );
+    }
+
+    #[test]
+    fn nothing_marked() {
+        assert_eq!(
+            expand_str("up {marked} done", "/pics/a.png", &[]),
+            "up  done"
+        );
+    }
+
+    #[test]
+    fn other_braces_are_left_alone() {
+        assert_eq!(
+            expand_str("cp {path} ${HOME}/{size} {pat", "/a.png", &[]),
+            "cp '/a.png' ${HOME}/{size} {pat"
+        );
+        assert_eq!(expand_str("{{path}}", "/a.png", &[]), "{'/a.png'}");
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(
+            expand_str("{path}", "/pics/it's.png", &[]),
+            r"'/pics/it'\''s.png'"
+        );
+        assert_eq!(
+            expand_str("{path}", "/pics/$(rm x).png", &[]),
+            "'/pics/$(rm x).png'"
+        );
+    }
+
+    #[test]
+    fn awkward_names_reach_the_command_unchanged() {
+        for name in AWKWARD {
+            assert_eq!(shell_words("{path}", name, &[]), [name]);
+            assert_eq!(shell_words("{dir}/{name}", name, &[]), [name]);
+        }
+        assert_eq!(shell_words("{marked}", "/a.png", &AWKWARD), AWKWARD);
+    }
+}
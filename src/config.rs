@@ -1,16 +1,195 @@
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use toml_edit::{DocumentMut, ImDocument, Item};
 use winit::event::VirtualKeyCode;
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+use crate::fileops::Collision;
+use crate::formats::Format;
+use crate::sort::SortMode;
+
+/// The current config layout. Bump this and add a step to
+/// `ConfigManager::migrate` whenever existing entries are renamed or moved.
+pub const CONFIG_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
+    pub version: u32,
+    pub files: FileSettings,
+    pub raw: RawSettings,
+    pub color: ColorSettings,
+    pub book: BookSettings,
+    pub wallpaper: WallpaperSettings,
+    pub triage: TriageSettings,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub commands: Vec<CommandSettings>,
     pub keybinds: KeyBinds,
 }
 
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            files: FileSettings::default(),
+            raw: RawSettings::default(),
+            color: ColorSettings::default(),
+            book: BookSettings::default(),
+            wallpaper: WallpaperSettings::default(),
+            triage: TriageSettings::default(),
+            commands: Vec::new(),
+            keybinds: KeyBinds::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FileSettings {
+    /// Image formats shown when browsing a directory, by name or extension
+    pub formats: Vec<String>,
+    /// Step over files that fail to decode instead of showing a placeholder
+    pub skip_broken: bool,
+    /// What images are sorted by: natural, mtime, size, exif, dimensions or
+    /// random
+    pub sort: String,
+    /// Sort in descending order
+    pub descending: bool,
+    /// Seed for the random order; 0 picks a new one each time
+    pub seed: u64,
+    /// What moving or copying does when the destination already has a file
+    /// of the same name: ask, skip, rename or overwrite
+    pub on_collision: String,
+}
+
+impl Default for FileSettings {
+    fn default() -> Self {
+        Self {
+            formats: Format::supported()
+                .map(|format| format.name().to_string())
+                .collect(),
+            skip_broken: false,
+            sort: SortMode::Natural.name().to_string(),
+            descending: false,
+            seed: 0,
+            on_collision: Collision::Ask.name().to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RawSettings {
+    /// Demosaic the sensor data instead of showing the embedded JPEG preview
+    pub full_decode: bool,
+    /// Show a RAW and a JPEG with the same name as a single image
+    pub pair_with_jpeg: bool,
+}
+
+impl Default for RawSettings {
+    fn default() -> Self {
+        Self {
+            full_decode: false,
+            pair_with_jpeg: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ColorSettings {
+    /// Convert images to the display's color space using their ICC profiles
+    pub managed: bool,
+    /// ICC profile of the display; empty for sRGB
+    pub display_profile: String,
+}
+
+impl Default for ColorSettings {
+    fn default() -> Self {
+        Self {
+            managed: true,
+            display_profile: String::new(),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BookSettings {
+    /// Start in book mode, showing two pages side by side
+    pub enabled: bool,
+    /// Order spreads right to left, as manga are read
+    pub right_to_left: bool,
+    /// Show the first page on its own, as a book's cover is
+    pub cover_offset: bool,
+}
+
+impl Default for BookSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            right_to_left: false,
+            cover_offset: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WallpaperSettings {
+    /// Seconds each image stays up when rotating through marked images
+    pub rotation_interval: u64,
+}
+
+impl Default for WallpaperSettings {
+    fn default() -> Self {
+        Self {
+            rotation_interval: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TriageSettings {
+    /// Copy images into the slot folders instead of moving them
+    pub copy: bool,
+    /// Folders keyed by the number key that sorts an image into them
+    #[serde(flatten)]
+    pub slots: BTreeMap<String, String>,
+}
+
+impl TriageSettings {
+    /// Slots are named after the number keys 1 to 9.
+    pub fn is_slot(name: &str) -> bool {
+        matches!(name.as_bytes(), [b'1'..=b'9'])
+    }
+}
+
+/// An external program the current or marked images can be sent to, from a
+/// `[[commands]]` entry.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CommandSettings {
+    /// Shown in notifications
+    pub name: String,
+    /// Keys that run it, in the same form as a keybinding
+    pub key: String,
+    /// Run with `sh -c` once {path}, {dir}, {name} and {marked} are filled in
+    pub command: String,
+    /// Wait for the command to finish and report whether it succeeded
+    pub wait: bool,
+    /// Show the images again once the command has finished, for editors
+    /// that save over them; implies `wait`
+    pub reload: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct KeyBinds {
     #[serde(rename = "PreviousImage")]
     pub previous_image: String,
@@ -28,6 +207,80 @@ pub struct KeyBinds {
     pub cancel_delete: String,
     #[serde(rename = "AlwaysDelete")]
     pub always_delete: String,
+    #[serde(rename = "ToggleBrokenFilter")]
+    pub toggle_broken_filter: String,
+    #[serde(rename = "ZoomIn")]
+    pub zoom_in: String,
+    #[serde(rename = "ZoomOut")]
+    pub zoom_out: String,
+    #[serde(rename = "ResetZoom")]
+    pub reset_zoom: String,
+    #[serde(rename = "NextPage")]
+    pub next_page: String,
+    #[serde(rename = "PreviousPage")]
+    pub previous_page: String,
+    #[serde(rename = "ExposureUp")]
+    pub exposure_up: String,
+    #[serde(rename = "ExposureDown")]
+    pub exposure_down: String,
+    #[serde(rename = "GammaUp")]
+    pub gamma_up: String,
+    #[serde(rename = "GammaDown")]
+    pub gamma_down: String,
+    #[serde(rename = "ResetExposure")]
+    pub reset_exposure: String,
+    #[serde(rename = "ToggleColorManagement")]
+    pub toggle_color_management: String,
+    #[serde(rename = "ToggleBookMode")]
+    pub toggle_book_mode: String,
+    #[serde(rename = "ToggleReadingDirection")]
+    pub toggle_reading_direction: String,
+    #[serde(rename = "ToggleCoverOffset")]
+    pub toggle_cover_offset: String,
+    #[serde(rename = "ToggleRecursive")]
+    pub toggle_recursive: String,
+    #[serde(rename = "NextFolder")]
+    pub next_folder: String,
+    #[serde(rename = "PreviousFolder")]
+    pub previous_folder: String,
+    #[serde(rename = "CycleSort")]
+    pub cycle_sort: String,
+    #[serde(rename = "ReverseSort")]
+    pub reverse_sort: String,
+    #[serde(rename = "ToggleFollowNewest")]
+    pub toggle_follow_newest: String,
+    #[serde(rename = "ToggleMark")]
+    pub toggle_mark: String,
+    #[serde(rename = "ToggleMarkAll")]
+    pub toggle_mark_all: String,
+    #[serde(rename = "InvertMarks")]
+    pub invert_marks: String,
+    #[serde(rename = "MoveImages")]
+    pub move_images: String,
+    #[serde(rename = "CopyImages")]
+    pub copy_images: String,
+    #[serde(rename = "RotateClockwise")]
+    pub rotate_clockwise: String,
+    #[serde(rename = "RotateCounterclockwise")]
+    pub rotate_counterclockwise: String,
+    #[serde(rename = "SkipExisting")]
+    pub skip_existing: String,
+    #[serde(rename = "RenameExisting")]
+    pub rename_existing: String,
+    #[serde(rename = "OverwriteExisting")]
+    pub overwrite_existing: String,
+    #[serde(rename = "RenameImages")]
+    pub rename_images: String,
+    #[serde(rename = "CopyImageToClipboard")]
+    pub copy_image_to_clipboard: String,
+    #[serde(rename = "CopyPathToClipboard")]
+    pub copy_path_to_clipboard: String,
+    #[serde(rename = "CopyUriToClipboard")]
+    pub copy_uri_to_clipboard: String,
+    #[serde(rename = "PasteFromClipboard")]
+    pub paste_from_clipboard: String,
+    #[serde(rename = "SaveImageAs")]
+    pub save_image_as: String,
 }
 
 impl Default for KeyBinds {
@@ -41,114 +294,1089 @@ impl Default for KeyBinds {
             confirm_delete: "Y".to_string(),
             cancel_delete: "N, Escape".to_string(),
             always_delete: "A".to_string(),
+            toggle_broken_filter: "B".to_string(),
+            zoom_in: "Equals, Plus, NumpadAdd".to_string(),
+            zoom_out: "Minus, NumpadSubtract".to_string(),
+            reset_zoom: "0".to_string(),
+            next_page: "J, PageDown".to_string(),
+            previous_page: "K, PageUp".to_string(),
+            exposure_up: "RBracket".to_string(),
+            exposure_down: "LBracket".to_string(),
+            gamma_up: "Period".to_string(),
+            gamma_down: "Comma".to_string(),
+            reset_exposure: "Backslash".to_string(),
+            toggle_color_management: "I".to_string(),
+            toggle_book_mode: "S".to_string(),
+            toggle_reading_direction: "T".to_string(),
+            toggle_cover_offset: "O".to_string(),
+            toggle_recursive: "R".to_string(),
+            next_folder: "Down".to_string(),
+            previous_folder: "Up".to_string(),
+            cycle_sort: "Tab".to_string(),
+            reverse_sort: "V".to_string(),
+            toggle_follow_newest: "F".to_string(),
+            toggle_mark: "M".to_string(),
+            toggle_mark_all: "A".to_string(),
+            invert_marks: "N".to_string(),
+            move_images: "X".to_string(),
+            copy_images: "C".to_string(),
+            rotate_clockwise: "E".to_string(),
+            rotate_counterclockwise: "Q".to_string(),
+            skip_existing: "S, Escape".to_string(),
+            rename_existing: "R".to_string(),
+            overwrite_existing: "O".to_string(),
+            rename_images: "F2".to_string(),
+            copy_image_to_clipboard: "Y".to_string(),
+            copy_path_to_clipboard: "P".to_string(),
+            copy_uri_to_clipboard: "U".to_string(),
+            paste_from_clipboard: "Insert".to_string(),
+            save_image_as: "F3".to_string(),
         }
     }
 }
 
+/// Which part of the UI a keybinding is active in. The same key may be bound
+/// once per context, e.g. `Escape` exits normally but cancels a delete prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyContext {
+    Normal,
+    DeleteConfirmation,
+    Collision,
+}
+
+/// Every bindable action as `(config key, action name, context)`.
+const ACTIONS: &[(&str, &str, KeyContext)] = &[
+    ("PreviousImage", "previous_image", KeyContext::Normal),
+    ("NextImage", "next_image", KeyContext::Normal),
+    ("Exit", "exit", KeyContext::Normal),
+    ("SetWallpaper", "set_wallpaper", KeyContext::Normal),
+    ("DeleteImage", "delete_image", KeyContext::Normal),
+    (
+        "ToggleBrokenFilter",
+        "toggle_broken_filter",
+        KeyContext::Normal,
+    ),
+    ("ZoomIn", "zoom_in", KeyContext::Normal),
+    ("ZoomOut", "zoom_out", KeyContext::Normal),
+    ("ResetZoom", "reset_zoom", KeyContext::Normal),
+    ("NextPage", "next_page", KeyContext::Normal),
+    ("PreviousPage", "previous_page", KeyContext::Normal),
+    ("ExposureUp", "exposure_up", KeyContext::Normal),
+    ("ExposureDown", "exposure_down", KeyContext::Normal),
+    ("GammaUp", "gamma_up", KeyContext::Normal),
+    ("GammaDown", "gamma_down", KeyContext::Normal),
+    ("ResetExposure", "reset_exposure", KeyContext::Normal),
+    (
+        "ToggleColorManagement",
+        "toggle_color_management",
+        KeyContext::Normal,
+    ),
+    ("ToggleBookMode", "toggle_book_mode", KeyContext::Normal),
+    (
+        "ToggleReadingDirection",
+        "toggle_reading_direction",
+        KeyContext::Normal,
+    ),
+    (
+        "ToggleCoverOffset",
+        "toggle_cover_offset",
+        KeyContext::Normal,
+    ),
+    ("ToggleRecursive", "toggle_recursive", KeyContext::Normal),
+    ("NextFolder", "next_folder", KeyContext::Normal),
+    ("PreviousFolder", "previous_folder", KeyContext::Normal),
+    ("CycleSort", "cycle_sort", KeyContext::Normal),
+    ("ReverseSort", "reverse_sort", KeyContext::Normal),
+    (
+        "ToggleFollowNewest",
+        "toggle_follow_newest",
+        KeyContext::Normal,
+    ),
+    ("ToggleMark", "toggle_mark", KeyContext::Normal),
+    ("ToggleMarkAll", "toggle_mark_all", KeyContext::Normal),
+    ("InvertMarks", "invert_marks", KeyContext::Normal),
+    ("MoveImages", "move_images", KeyContext::Normal),
+    ("CopyImages", "copy_images", KeyContext::Normal),
+    ("RotateClockwise", "rotate_clockwise", KeyContext::Normal),
+    (
+        "RotateCounterclockwise",
+        "rotate_counterclockwise",
+        KeyContext::Normal,
+    ),
+    ("RenameImages", "rename_images", KeyContext::Normal),
+    (
+        "CopyImageToClipboard",
+        "copy_image_to_clipboard",
+        KeyContext::Normal,
+    ),
+    (
+        "CopyPathToClipboard",
+        "copy_path_to_clipboard",
+        KeyContext::Normal,
+    ),
+    (
+        "CopyUriToClipboard",
+        "copy_uri_to_clipboard",
+        KeyContext::Normal,
+    ),
+    (
+        "PasteFromClipboard",
+        "paste_from_clipboard",
+        KeyContext::Normal,
+    ),
+    ("SaveImageAs", "save_image_as", KeyContext::Normal),
+    (
+        "ConfirmDelete",
+        "confirm_delete",
+        KeyContext::DeleteConfirmation,
+    ),
+    (
+        "CancelDelete",
+        "cancel_delete",
+        KeyContext::DeleteConfirmation,
+    ),
+    (
+        "AlwaysDelete",
+        "always_delete",
+        KeyContext::DeleteConfirmation,
+    ),
+    ("SkipExisting", "skip_existing", KeyContext::Collision),
+    ("RenameExisting", "rename_existing", KeyContext::Collision),
+    (
+        "OverwriteExisting",
+        "overwrite_existing",
+        KeyContext::Collision,
+    ),
+];
+
+/// The normal action a keybinding name such as `ToggleMark` stands for, so
+/// actions can be run by name. The action's own name (`toggle_mark`) works
+/// too.
+pub fn normal_action(name: &str) -> Option<&'static str> {
+    ACTIONS
+        .iter()
+        .find(|&&(config_key, action, context)| {
+            context == KeyContext::Normal
+                && (config_key.eq_ignore_ascii_case(name) || action == name)
+        })
+        .map(|&(_, action, _)| action)
+}
+
+impl KeyBinds {
+    fn get(&self, config_key: &str) -> Option<&String> {
+        match config_key {
+            "PreviousImage" => Some(&self.previous_image),
+            "NextImage" => Some(&self.next_image),
+            "Exit" => Some(&self.exit),
+            "SetWallpaper" => Some(&self.set_wallpaper),
+            "DeleteImage" => Some(&self.delete_image),
+            "ConfirmDelete" => Some(&self.confirm_delete),
+            "CancelDelete" => Some(&self.cancel_delete),
+            "AlwaysDelete" => Some(&self.always_delete),
+            "ToggleBrokenFilter" => Some(&self.toggle_broken_filter),
+            "ZoomIn" => Some(&self.zoom_in),
+            "ZoomOut" => Some(&self.zoom_out),
+            "ResetZoom" => Some(&self.reset_zoom),
+            "NextPage" => Some(&self.next_page),
+            "PreviousPage" => Some(&self.previous_page),
+            "ExposureUp" => Some(&self.exposure_up),
+            "ExposureDown" => Some(&self.exposure_down),
+            "GammaUp" => Some(&self.gamma_up),
+            "GammaDown" => Some(&self.gamma_down),
+            "ResetExposure" => Some(&self.reset_exposure),
+            "ToggleColorManagement" => Some(&self.toggle_color_management),
+            "ToggleBookMode" => Some(&self.toggle_book_mode),
+            "ToggleReadingDirection" => Some(&self.toggle_reading_direction),
+            "ToggleCoverOffset" => Some(&self.toggle_cover_offset),
+            "ToggleRecursive" => Some(&self.toggle_recursive),
+            "NextFolder" => Some(&self.next_folder),
+            "PreviousFolder" => Some(&self.previous_folder),
+            "CycleSort" => Some(&self.cycle_sort),
+            "ReverseSort" => Some(&self.reverse_sort),
+            "ToggleFollowNewest" => Some(&self.toggle_follow_newest),
+            "ToggleMark" => Some(&self.toggle_mark),
+            "ToggleMarkAll" => Some(&self.toggle_mark_all),
+            "InvertMarks" => Some(&self.invert_marks),
+            "MoveImages" => Some(&self.move_images),
+            "CopyImages" => Some(&self.copy_images),
+            "RotateClockwise" => Some(&self.rotate_clockwise),
+            "RotateCounterclockwise" => Some(&self.rotate_counterclockwise),
+            "SkipExisting" => Some(&self.skip_existing),
+            "RenameExisting" => Some(&self.rename_existing),
+            "OverwriteExisting" => Some(&self.overwrite_existing),
+            "RenameImages" => Some(&self.rename_images),
+            "CopyImageToClipboard" => Some(&self.copy_image_to_clipboard),
+            "CopyPathToClipboard" => Some(&self.copy_path_to_clipboard),
+            "CopyUriToClipboard" => Some(&self.copy_uri_to_clipboard),
+            "PasteFromClipboard" => Some(&self.paste_from_clipboard),
+            "SaveImageAs" => Some(&self.save_image_as),
+            _ => None,
+        }
+    }
+}
+
+/// Expands a leading `~` in a path from the config to the home directory.
+pub fn expand_path(path: &str) -> PathBuf {
+    match path.strip_prefix("~") {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => dirs::home_dir()
+            .map(|home| home.join(rest.trim_start_matches('/')))
+            .unwrap_or_else(|| PathBuf::from(path)),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Where the user's config file comes from.
+#[derive(Debug, Clone)]
+pub enum ConfigSource {
+    /// `$XDG_CONFIG_HOME/eleviewr/config.toml`, created on first run
+    UserDefault,
+    /// An explicit file from `--config` or `ELEVIEWR_CONFIG`
+    File(PathBuf),
+    /// Built-in defaults only; no file is read or written
+    None,
+}
+
+impl ConfigSource {
+    /// Resolves the source in order of precedence: `--no-config`, `--config`,
+    /// the `ELEVIEWR_CONFIG` environment variable, then the XDG default.
+    pub fn from_args(config: Option<PathBuf>, no_config: bool) -> Self {
+        if no_config {
+            return Self::None;
+        }
+
+        match config.or_else(|| std::env::var_os("ELEVIEWR_CONFIG").map(PathBuf::from)) {
+            Some(path) => Self::File(path),
+            None => Self::UserDefault,
+        }
+    }
+}
+
+/// A problem found in a config file, with the 1-based line it occurs on.
+#[derive(Debug, Clone)]
+pub struct ConfigDiagnostic {
+    pub file: PathBuf,
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl ConfigDiagnostic {
+    fn new(file: &Path, content: &str, span: Option<Range<usize>>, message: String) -> Self {
+        Self {
+            file: file.to_path_buf(),
+            line: span.map(|span| line_of(content, span.start)),
+            message,
+        }
+    }
+}
+
+impl fmt::Display for ConfigDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file.display(), line, self.message),
+            None => write!(f, "{}: {}", self.file.display(), self.message),
+        }
+    }
+}
+
+/// Comments out every line of some TOML, leaving blank lines blank.
+fn commented(toml: &str) -> String {
+    toml.lines()
+        .map(|line| match line {
+            "" => "\n".to_string(),
+            line => format!("# {}\n", line),
+        })
+        .collect()
+}
+
+/// The `(table, key)` of each entry a file mentions only in a comment, such
+/// as the commented-out defaults it was created with.
+fn commented_keys(content: &str) -> HashSet<(String, String)> {
+    let mut table = String::new();
+    let mut keys = HashSet::new();
+    for line in content.lines() {
+        let line = line.trim_start();
+        let (comment, line) = match line.strip_prefix('#') {
+            Some(rest) => (true, rest.trim_start()),
+            None => (false, line),
+        };
+        if let Some(header) = line.strip_prefix('[') {
+            let name = header.trim_start_matches('[').split(']').next();
+            table = name.unwrap_or_default().trim().to_string();
+        } else if let Some((key, _)) = line.split_once('=').filter(|_| comment) {
+            keys.insert((table.clone(), key.trim().trim_matches('"').to_string()));
+        }
+    }
+    keys
+}
+
+fn line_of(content: &str, offset: usize) -> usize {
+    content[..offset].matches('\n').count() + 1
+}
+
+/// Accumulates the valid entries of each config file, later files overriding
+/// earlier ones, along with every problem found along the way.
+#[derive(Default)]
+struct ConfigLayers {
+    accepted: toml::Table,
+    locations: HashMap<String, (PathBuf, usize)>,
+    diagnostics: Vec<ConfigDiagnostic>,
+}
+
+impl ConfigLayers {
+    /// Records a problem with a file as a whole rather than one of its lines.
+    fn warn(&mut self, file: &Path, message: String) {
+        self.diagnostics.push(ConfigDiagnostic {
+            file: file.to_path_buf(),
+            line: None,
+            message,
+        });
+    }
+
+    /// Parses one file leniently: every problem is reported as a diagnostic
+    /// and the offending entry is skipped, so one bad field never discards
+    /// the rest of the user's settings.
+    fn add(&mut self, file: &Path, content: &str) {
+        let diagnostic = |span: Option<Range<usize>>, message: String| {
+            ConfigDiagnostic::new(file, content, span, message)
+        };
+
+        let document = match ImDocument::parse(content) {
+            Ok(document) => document,
+            Err(e) => {
+                self.diagnostics.push(diagnostic(
+                    e.span(),
+                    e.message().trim_end().replace('\n', "; "),
+                ));
+                return;
+            }
+        };
+        let values = match content.parse::<toml::Table>() {
+            Ok(values) => values,
+            Err(e) => {
+                self.diagnostics.push(diagnostic(
+                    e.span(),
+                    e.message().trim_end().replace('\n', "; "),
+                ));
+                return;
+            }
+        };
+
+        let defaults = toml::Table::try_from(Config::default()).unwrap_or_default();
+
+        for (name, item) in document.iter() {
+            let key_span = document.key(name).and_then(|key| key.span());
+            match name {
+                "version" => match item.as_integer() {
+                    Some(version) if version > CONFIG_VERSION as i64 => {
+                        self.diagnostics.push(diagnostic(
+                            item.span().or(key_span),
+                            format!(
+                                "config version {} is newer than this EleViewr supports ({})",
+                                version, CONFIG_VERSION
+                            ),
+                        ));
+                    }
+                    Some(version) if version < 0 => {
+                        self.diagnostics.push(diagnostic(
+                            item.span().or(key_span),
+                            format!("config version {} is invalid", version),
+                        ));
+                    }
+                    Some(_) => {}
+                    None => {
+                        self.diagnostics.push(diagnostic(
+                            item.span().or(key_span),
+                            "'version' must be an integer".to_string(),
+                        ));
+                    }
+                },
+                "keybinds" => self.add_keybinds(file, content, item, key_span),
+                "commands" => self.add_commands(file, content, item, values.get(name), key_span),
+                _ => {
+                    let Some(section_defaults) = defaults.get(name).and_then(|v| v.as_table())
+                    else {
+                        self.diagnostics
+                            .push(diagnostic(key_span, format!("unknown key '{}'", name)));
+                        continue;
+                    };
+
+                    let (Some(section), Some(section_values)) = (
+                        item.as_table_like(),
+                        values.get(name).and_then(|v| v.as_table()),
+                    ) else {
+                        self.diagnostics.push(diagnostic(
+                            item.span().or(key_span),
+                            format!("'{}' must be a table", name),
+                        ));
+                        continue;
+                    };
+
+                    for (key, value) in section_values {
+                        let span = section.get(key).and_then(|item| item.span());
+                        let slot = name == "triage" && TriageSettings::is_slot(key);
+                        if !section_defaults.contains_key(key) && !slot {
+                            self.diagnostics.push(diagnostic(
+                                section.key(key).and_then(|key| key.span()).or(span),
+                                format!("unknown key '{}.{}'", name, key),
+                            ));
+                            continue;
+                        }
+
+                        // Deserialize the entry on its own so a type error
+                        // only rejects this one value
+                        let mut probe = toml::Table::new();
+                        probe.insert(
+                            name.to_string(),
+                            toml::Table::from_iter([(key.clone(), value.clone())]).into(),
+                        );
+                        if let Err(e) = probe.try_into::<Config>() {
+                            self.diagnostics.push(diagnostic(
+                                span,
+                                format!(
+                                    "invalid value for '{}.{}': {}",
+                                    name,
+                                    key,
+                                    e.message().trim_end()
+                                ),
+                            ));
+                            continue;
+                        }
+
+                        for problem in Self::check_value(name, key, value) {
+                            self.diagnostics.push(diagnostic(span.clone(), problem));
+                        }
+
+                        if let toml::Value::Table(accepted) = self
+                            .accepted
+                            .entry(name)
+                            .or_insert_with(|| toml::Table::new().into())
+                        {
+                            accepted.insert(key.clone(), value.clone());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn add_keybinds(
+        &mut self,
+        file: &Path,
+        content: &str,
+        item: &toml_edit::Item,
+        key_span: Option<Range<usize>>,
+    ) {
+        let diagnostic = |span: Option<Range<usize>>, message: String| {
+            ConfigDiagnostic::new(file, content, span, message)
+        };
+
+        let Some(keybinds) = item.as_table_like() else {
+            self.diagnostics.push(diagnostic(
+                item.span().or(key_span),
+                "'keybinds' must be a table".to_string(),
+            ));
+            return;
+        };
+
+        for (action, value) in keybinds.iter() {
+            let action_span = keybinds.key(action).and_then(|key| key.span());
+            if !ACTIONS
+                .iter()
+                .any(|&(config_key, _, _)| config_key == action)
+            {
+                self.diagnostics.push(diagnostic(
+                    action_span,
+                    format!("unknown action 'keybinds.{}'", action),
+                ));
+                continue;
+            }
+
+            let Some(keys) = value.as_str() else {
+                self.diagnostics.push(diagnostic(
+                    value.span().or(action_span),
+                    format!("'{}' must be a string of comma-separated keys", action),
+                ));
+                continue;
+            };
+
+            for key in keys.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+                if ConfigManager::string_to_keycode(key).is_none() {
+                    self.diagnostics.push(diagnostic(
+                        value.span().or(action_span.clone()),
+                        format!("unknown key name '{}' in '{}'", key, action),
+                    ));
+                }
+            }
+
+            if let toml::Value::Table(accepted) = self
+                .accepted
+                .entry("keybinds")
+                .or_insert_with(|| toml::Table::new().into())
+            {
+                accepted.insert(action.to_string(), keys.into());
+            }
+            if let Some(span) = action_span {
+                self.locations.insert(
+                    action.to_string(),
+                    (file.to_path_buf(), line_of(content, span.start)),
+                );
+            }
+        }
+    }
+
+    /// Takes the usable `[[commands]]` entries and reports the rest. Like
+    /// any other value, the commands of a later file replace those of an
+    /// earlier one.
+    fn add_commands(
+        &mut self,
+        file: &Path,
+        content: &str,
+        item: &toml_edit::Item,
+        value: Option<&toml::Value>,
+        key_span: Option<Range<usize>>,
+    ) {
+        let diagnostic = |span: Option<Range<usize>>, message: String| {
+            ConfigDiagnostic::new(file, content, span, message)
+        };
+
+        let Some(toml::Value::Array(entries)) = value else {
+            self.diagnostics.push(diagnostic(
+                item.span().or(key_span),
+                "'commands' must be a list of [[commands]] tables".to_string(),
+            ));
+            return;
+        };
+
+        let mut accepted = Vec::new();
+        for (i, entry) in entries.iter().enumerate() {
+            // Point at the entry's own [[commands]] header where it has one
+            let table_item = item.as_array_of_tables().and_then(|tables| tables.get(i));
+            let span = table_item
+                .and_then(|table| table.span())
+                .or_else(|| item.span())
+                .or(key_span.clone());
+            let key_span = |key: &str| {
+                table_item
+                    .and_then(|table| table.key(key))
+                    .and_then(|key| key.span())
+                    .or(span.clone())
+            };
+
+            let Some(table) = entry.as_table() else {
+                self.diagnostics.push(diagnostic(
+                    span.clone(),
+                    "each command must be a table".to_string(),
+                ));
+                continue;
+            };
+            for key in table.keys() {
+                if !["name", "key", "command", "wait", "reload"].contains(&key.as_str()) {
+                    self.diagnostics.push(diagnostic(
+                        key_span(key),
+                        format!("unknown key 'commands.{}'", key),
+                    ));
+                }
+            }
+
+            let command = match entry.clone().try_into::<CommandSettings>() {
+                Ok(command) => command,
+                Err(e) => {
+                    self.diagnostics.push(diagnostic(
+                        span.clone(),
+                        format!("invalid command: {}", e.message().trim_end()),
+                    ));
+                    continue;
+                }
+            };
+            if command.name.is_empty() || command.command.is_empty() {
+                self.diagnostics.push(diagnostic(
+                    span.clone(),
+                    "a command needs both a 'name' and a 'command' to run".to_string(),
+                ));
+                continue;
+            }
+            for key in command
+                .key
+                .split(',')
+                .map(str::trim)
+                .filter(|k| !k.is_empty())
+            {
+                if ConfigManager::string_to_keycode(key).is_none() {
+                    self.diagnostics.push(diagnostic(
+                        key_span("key"),
+                        format!("unknown key name '{}' in command '{}'", key, command.name),
+                    ));
+                }
+            }
+
+            if let Some(span) = key_span("key") {
+                self.locations.insert(
+                    format!("commands.{}", accepted.len()),
+                    (file.to_path_buf(), line_of(content, span.start)),
+                );
+            }
+            accepted.push(entry.clone());
+        }
+        self.accepted
+            .insert("commands".to_string(), toml::Value::Array(accepted));
+    }
+
+    /// Checks that go beyond the value's type, such as names that must refer
+    /// to something this build knows about.
+    fn check_value(section: &str, key: &str, value: &toml::Value) -> Vec<String> {
+        match (section, key) {
+            ("files", "formats") => value
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|name| name.as_str())
+                .filter_map(|name| match Format::from_name(name) {
+                    None => Some(format!("unknown image format '{}'", name)),
+                    Some(format) if !format.is_enabled() => Some(format!(
+                        "image format '{}' is not supported by this build",
+                        name
+                    )),
+                    Some(_) => None,
+                })
+                .collect(),
+            ("files", "sort") => match value.as_str() {
+                Some(name) if SortMode::from_name(name).is_none() => {
+                    vec![format!("unknown sort order '{}'", name)]
+                }
+                _ => Vec::new(),
+            },
+            ("files", "on_collision") => match value.as_str() {
+                Some(name) if Collision::from_name(name).is_none() => {
+                    vec![format!("unknown collision handling '{}'", name)]
+                }
+                _ => Vec::new(),
+            },
+            ("wallpaper", "rotation_interval") => match value.as_integer() {
+                Some(0) => vec!["rotation_interval must be at least 1 second".to_string()],
+                _ => Vec::new(),
+            },
+            ("color", "display_profile") => match value.as_str() {
+                Some("") | None => Vec::new(),
+                Some(_) if !cfg!(feature = "icc") => {
+                    vec!["color management is not supported by this build".to_string()]
+                }
+                Some(path) if !expand_path(path).is_file() => {
+                    vec![format!("display profile '{}' does not exist", path)]
+                }
+                Some(_) => Vec::new(),
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    /// Deserializes the merged entries, letting serde fill in defaults for
+    /// anything no file provided, then checks the result for key conflicts.
+    fn finish(mut self) -> (Config, Vec<ConfigDiagnostic>) {
+        let config: Config = self.accepted.try_into().unwrap_or_default();
+
+        for (key, first, second) in ConfigManager::find_duplicate_bindings(&config.keybinds) {
+            let message = if first == second {
+                format!("key '{:?}' is listed more than once in '{}'", key, first)
+            } else {
+                format!(
+                    "key '{:?}' is bound to both '{}' and '{}'; '{}' keeps it and '{}' loses it",
+                    key, first, second, first, second
+                )
+            };
+            // Point at whichever side a file set, preferring the one that
+            // loses the key. Conflicts between two defaults have no file to
+            // point at.
+            let location = self
+                .locations
+                .get(second)
+                .or_else(|| self.locations.get(first));
+            if let Some((file, line)) = location {
+                self.diagnostics.push(ConfigDiagnostic {
+                    file: file.clone(),
+                    line: Some(*line),
+                    message,
+                });
+            }
+        }
+
+        // Commands only get the keys nothing else uses
+        let keybind_map = ConfigManager::build_keybind_map(&config.keybinds);
+        for (i, command) in config.commands.iter().enumerate() {
+            for key in ConfigManager::parse_keys(&command.key) {
+                let taken_by = keybind_map
+                    .get(&(KeyContext::Normal, key))
+                    .map(|action| format!("'{}'", action))
+                    .or_else(|| {
+                        config
+                            .triage
+                            .slots
+                            .iter()
+                            .filter(|(_, folder)| !folder.is_empty())
+                            .map(|(slot, _)| slot)
+                            .find(|slot| ConfigManager::string_to_keycode(slot) == Some(key))
+                            .map(|slot| format!("triage slot {}", slot))
+                    });
+                let Some(taken_by) = taken_by else {
+                    continue;
+                };
+                if let Some((file, line)) = self.locations.get(&format!("commands.{}", i)) {
+                    self.diagnostics.push(ConfigDiagnostic {
+                        file: file.clone(),
+                        line: Some(*line),
+                        message: format!(
+                            "key '{:?}' of command '{}' is already bound to {}; {} wins",
+                            key, command.name, taken_by, taken_by
+                        ),
+                    });
+                }
+            }
+        }
+
+        (config, self.diagnostics)
+    }
+}
+
 pub struct ConfigManager {
-    #[allow(dead_code)]
     config: Config,
-    keybind_map: HashMap<VirtualKeyCode, String>,
+    keybind_map: HashMap<(KeyContext, VirtualKeyCode), String>,
+    diagnostics: Vec<ConfigDiagnostic>,
 }
 
 impl ConfigManager {
-    pub fn new() -> Result<Self> {
-        let config = Self::load_config()?;
+    pub fn new(source: &ConfigSource) -> Result<Self> {
+        let (config, diagnostics) = Self::load_config(source)?;
+        Ok(Self::from_config(config, diagnostics))
+    }
+
+    fn from_config(config: Config, diagnostics: Vec<ConfigDiagnostic>) -> Self {
         let keybind_map = Self::build_keybind_map(&config.keybinds);
 
-        Ok(Self {
+        Self {
             config,
             keybind_map,
-        })
+            diagnostics,
+        }
     }
 
-    fn get_config_path() -> Result<PathBuf> {
+    /// The per-user config file. Nothing is created until it is first saved.
+    pub fn user_config_path() -> Result<PathBuf> {
         let config_dir = dirs::config_dir()
             .context("Could not find config directory")?
             .join("eleviewr");
 
-        fs::create_dir_all(&config_dir).context("Failed to create config directory")?;
-
         Ok(config_dir.join("config.toml"))
     }
 
-    fn load_config() -> Result<Config> {
-        let config_path = Self::get_config_path()?;
+    /// System-wide config files from `$XDG_CONFIG_DIRS` (default `/etc/xdg`),
+    /// lowest precedence first.
+    fn system_config_paths() -> Vec<PathBuf> {
+        let dirs = std::env::var("XDG_CONFIG_DIRS")
+            .ok()
+            .filter(|dirs| !dirs.is_empty())
+            .unwrap_or_else(|| "/etc/xdg".to_string());
+
+        dirs.split(':')
+            .filter(|dir| !dir.is_empty())
+            .rev()
+            .map(|dir| Path::new(dir).join("eleviewr").join("config.toml"))
+            .collect()
+    }
+
+    /// Every file read for `source`, lowest precedence first, with the user's
+    /// own file last.
+    pub fn config_paths(source: &ConfigSource) -> Result<Vec<PathBuf>> {
+        let user_path = match source {
+            ConfigSource::None => return Ok(Vec::new()),
+            ConfigSource::UserDefault => Self::user_config_path()?,
+            ConfigSource::File(path) => path.clone(),
+        };
 
-        if !config_path.exists() {
-            let default_config = Config::default();
-            Self::save_config(&default_config)?;
-            return Ok(default_config);
+        let mut paths = Self::system_config_paths();
+        paths.push(user_path);
+        Ok(paths)
+    }
+
+    fn load_config(source: &ConfigSource) -> Result<(Config, Vec<ConfigDiagnostic>)> {
+        Self::load_files(source, &Self::config_paths(source)?)
+    }
+
+    /// Loads `paths` as found by `config_paths`, with the user's file last.
+    fn load_files(
+        source: &ConfigSource,
+        paths: &[PathBuf],
+    ) -> Result<(Config, Vec<ConfigDiagnostic>)> {
+        let mut layers = ConfigLayers::default();
+
+        let Some((user_path, system_paths)) = paths.split_last() else {
+            return Ok(layers.finish());
+        };
+
+        // An unreadable system file only loses its own settings
+        for path in system_paths.iter().filter(|path| path.exists()) {
+            match fs::read_to_string(path) {
+                Ok(content) => layers.add(path, &content),
+                Err(e) => layers.warn(path, format!("could not read the config file: {}", e)),
+            }
         }
 
-        let config_content =
-            fs::read_to_string(&config_path).context("Failed to read config file")?;
+        if !user_path.exists() {
+            if let ConfigSource::File(path) = source {
+                return Err(anyhow!("Config file not found: {}", path.display()));
+            }
+            // Settings still load without the file, so failing to create
+            // it is only worth a warning
+            if let Err(e) = Self::create_user_config(user_path) {
+                layers.warn(
+                    user_path,
+                    format!("could not create the config file: {}", e.root_cause()),
+                );
+            }
+            return Ok(layers.finish());
+        }
 
-        let config: Config =
-            toml::from_str(&config_content).context("Failed to parse config file")?;
+        let mut content = fs::read_to_string(user_path)
+            .with_context(|| format!("Failed to read config file {}", user_path.display()))?;
 
-        Ok(config)
+        // A file given with --config or ELEVIEWR_CONFIG may be shared or
+        // kept in version control, so it is only upgraded in memory
+        // A read-only file, such as one managed by a dotfiles tool, is also
+        // upgraded in memory only
+        if let Some(upgraded) = Self::upgrade_document(&content) {
+            if let ConfigSource::UserDefault = source {
+                if let Err(e) = fs::write(user_path, &upgraded) {
+                    layers.warn(
+                        user_path,
+                        format!("could not save the upgraded config: {}", e),
+                    );
+                }
+            }
+            content = upgraded;
+        }
+
+        layers.add(user_path, &content);
+        Ok(layers.finish())
+    }
+
+    /// Reads and validates every config file for `source` without applying
+    /// or writing anything, for `--check-config`. Returns the files that were
+    /// checked along with the problems found.
+    pub fn check_config(source: &ConfigSource) -> Result<(Vec<PathBuf>, Vec<ConfigDiagnostic>)> {
+        let mut layers = ConfigLayers::default();
+        let mut checked = Vec::new();
+
+        let paths = Self::config_paths(source)?;
+        let user_path = paths.last().cloned();
+        for path in paths {
+            if !path.exists() {
+                continue;
+            }
+            match fs::read_to_string(&path) {
+                Ok(content) => layers.add(&path, &content),
+                Err(e) if Some(&path) != user_path.as_ref() => {
+                    layers.warn(&path, format!("could not read the config file: {}", e));
+                }
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("Failed to read config file {}", path.display()))
+                }
+            }
+            checked.push(path);
+        }
+
+        Ok((checked, layers.finish().1))
+    }
+
+    /// Migrates an older config layout to `CONFIG_VERSION`. Besides the
+    /// version and entries a migration renames, settings the file leaves out
+    /// are only added as commented-out defaults, so they keep coming from the
+    /// defaults and system files. The document is edited in place so the
+    /// user's comments and formatting survive. Returns the new file contents,
+    /// or `None` if nothing needs to change.
+    fn upgrade_document(content: &str) -> Option<String> {
+        let mut document: DocumentMut = content.parse().ok()?;
+
+        // Files without a version predate versioning. Anything we can't read,
+        // including versions from a newer release or below 0, is left alone
+        // for the diagnostics to report.
+        let version = match document.get("version") {
+            Some(item) => item.as_integer()?,
+            None => 0,
+        };
+        Self::migrate(&mut document, version).ok()?;
+        if version < CONFIG_VERSION as i64 {
+            Self::add_missing_defaults(&mut document, content);
+        }
+
+        let upgraded = document.to_string();
+        (upgraded != content).then_some(upgraded)
+    }
+
+    /// Lists each setting and action the file has neither set nor mentioned
+    /// in a comment as a commented-out default, under its own table, so an
+    /// upgrade shows what the new release added.
+    fn add_missing_defaults(document: &mut DocumentMut, content: &str) {
+        let Ok(defaults) = toml::Table::try_from(Config::default()) else {
+            return;
+        };
+        let mentioned = commented_keys(content);
+
+        // The entries to add after each table of the file, by its position,
+        // and whole tables the file doesn't have
+        let mut blocks: HashMap<usize, String> = HashMap::new();
+        let mut new_tables = String::new();
+        for (section, values) in &defaults {
+            let Some(values) = values.as_table() else {
+                continue;
+            };
+            let table = document.get(section);
+            let missing: toml::Table = values
+                .iter()
+                .filter(|(key, _)| {
+                    !table
+                        .and_then(Item::as_table_like)
+                        .is_some_and(|table| table.contains_key(key))
+                        && !mentioned.contains(&(section.clone(), key.to_string()))
+                })
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            if missing.is_empty() {
+                continue;
+            }
+            let Ok(entries) = toml::to_string_pretty(&missing) else {
+                continue;
+            };
+
+            match table {
+                None => {
+                    new_tables.push_str(&format!("\n# [{}]\n", section));
+                    new_tables.push_str(&commented(&entries));
+                }
+                // Tables written inline or only through dotted keys have
+                // nowhere to put a comment
+                Some(table) => {
+                    if let Some(position) = table
+                        .as_table()
+                        .filter(|table| !table.is_implicit())
+                        .and_then(|table| table.position())
+                    {
+                        blocks.insert(position, commented(&entries));
+                    }
+                }
+            }
+        }
+
+        // Comments after a table's last entry belong to the next table's
+        // header, or to the end of the file after the last table
+        let mut tables: Vec<&mut toml_edit::Table> = Vec::new();
+        for (_, item) in document.iter_mut() {
+            match item {
+                Item::Table(table) => tables.push(table),
+                Item::ArrayOfTables(array) => tables.extend(array.iter_mut()),
+                _ => {}
+            }
+        }
+        tables.retain(|table| table.position().is_some());
+        tables.sort_by_key(|table| table.position());
+
+        let mut pending = None;
+        for table in tables {
+            if let Some(block) = pending.take() {
+                let prefix = table.decor().prefix().and_then(|prefix| prefix.as_str());
+                let prefix = format!("{}{}", block, prefix.unwrap_or_default());
+                table.decor_mut().set_prefix(prefix);
+            }
+            pending = table
+                .position()
+                .and_then(|position| blocks.remove(&position));
+        }
+        let trailing = document.trailing().as_str().unwrap_or_default();
+        let trailing = format!("{}{}{}", pending.unwrap_or_default(), trailing, new_tables);
+        document.set_trailing(trailing);
     }
 
-    fn save_config(config: &Config) -> Result<()> {
-        let config_path = Self::get_config_path()?;
-        let config_content =
-            toml::to_string_pretty(config).context("Failed to serialize config")?;
+    /// Applies each layout change between `from_version` and `CONFIG_VERSION`,
+    /// or fails without touching the document if there is no way there.
+    fn migrate(document: &mut DocumentMut, from_version: i64) -> Result<()> {
+        if !(0..=CONFIG_VERSION as i64).contains(&from_version) {
+            return Err(anyhow!("no migration from config version {}", from_version));
+        }
 
-        fs::write(&config_path, config_content).context("Failed to write config file")?;
+        for version in from_version..CONFIG_VERSION as i64 {
+            match version {
+                // Version 0 is the unversioned layout, which only lacks the
+                // `version` key itself
+                0 => {}
+                _ => return Err(anyhow!("no migration from config version {}", version)),
+            }
+        }
 
+        document["version"] = toml_edit::value(CONFIG_VERSION as i64);
         Ok(())
     }
 
-    fn build_keybind_map(keybinds: &KeyBinds) -> HashMap<VirtualKeyCode, String> {
-        let mut map = HashMap::new();
+    /// Finds keys bound more than once within the same context, as
+    /// `(key, first config key, later config key)`.
+    fn find_duplicate_bindings(
+        keybinds: &KeyBinds,
+    ) -> Vec<(VirtualKeyCode, &'static str, &'static str)> {
+        let mut seen: HashMap<(KeyContext, VirtualKeyCode), &'static str> = HashMap::new();
+        let mut duplicates = Vec::new();
 
-        // Parse each keybind string and map keys to actions
-        Self::parse_keys(&keybinds.previous_image)
-            .iter()
-            .for_each(|&key| {
-                map.insert(key, "previous_image".to_string());
-            });
+        for &(config_key, _, context) in ACTIONS {
+            let keys = keybinds.get(config_key).map(String::as_str).unwrap_or("");
+            for key in Self::parse_keys(keys) {
+                if let Some(&first) = seen.get(&(context, key)) {
+                    duplicates.push((key, first, config_key));
+                } else {
+                    seen.insert((context, key), config_key);
+                }
+            }
+        }
 
-        Self::parse_keys(&keybinds.next_image)
-            .iter()
-            .for_each(|&key| {
-                map.insert(key, "next_image".to_string());
-            });
+        duplicates
+    }
 
-        Self::parse_keys(&keybinds.exit).iter().for_each(|&key| {
-            map.insert(key, "exit".to_string());
-        });
+    fn create_user_config(config_path: &Path) -> Result<()> {
+        if let Some(config_dir) = config_path.parent() {
+            fs::create_dir_all(config_dir).context("Failed to create config directory")?;
+        }
 
-        Self::parse_keys(&keybinds.set_wallpaper)
-            .iter()
-            .for_each(|&key| {
-                map.insert(key, "set_wallpaper".to_string());
-            });
+        fs::write(config_path, Self::starter_config()?).context("Failed to write config file")?;
 
-        Self::parse_keys(&keybinds.delete_image)
-            .iter()
-            .for_each(|&key| {
-                map.insert(key, "delete_image".to_string());
-            });
+        Ok(())
+    }
 
-        Self::parse_keys(&keybinds.confirm_delete)
-            .iter()
-            .for_each(|&key| {
-                map.insert(key, "confirm_delete".to_string());
-            });
+    /// The user's config file as first created: just the version, with the
+    /// defaults commented out. Settings only take effect once uncommented, so
+    /// the system files and later releases can still change the rest.
+    fn starter_config() -> Result<String> {
+        let defaults =
+            toml::to_string_pretty(&Config::default()).context("Failed to serialize config")?;
 
-        Self::parse_keys(&keybinds.cancel_delete)
-            .iter()
-            .for_each(|&key| {
-                map.insert(key, "cancel_delete".to_string());
-            });
+        let defaults: String = defaults
+            .lines()
+            .filter(|line| !line.starts_with("version ="))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        Ok(format!(
+            "version = {}\n\n# Uncomment and edit the settings you want to change.\n{}",
+            CONFIG_VERSION,
+            commented(&defaults)
+        ))
+    }
 
-        Self::parse_keys(&keybinds.always_delete)
-            .iter()
-            .for_each(|&key| {
-                map.insert(key, "always_delete".to_string());
-            });
+    fn build_keybind_map(keybinds: &KeyBinds) -> HashMap<(KeyContext, VirtualKeyCode), String> {
+        let mut map = HashMap::new();
+
+        // Parse each keybind string and map keys to actions, keeping the first
+        // binding when a key is used twice in the same context
+        for &(config_key, action, context) in ACTIONS {
+            let keys = keybinds.get(config_key).map(String::as_str).unwrap_or("");
+            for key in Self::parse_keys(keys) {
+                map.entry((context, key))
+                    .or_insert_with(|| action.to_string());
+            }
+        }
 
         map
     }
@@ -198,19 +1426,74 @@ impl ConfigManager {
             "tab" => Some(VirtualKeyCode::Tab),
             "backspace" => Some(VirtualKeyCode::Back),
             "delete" => Some(VirtualKeyCode::Delete),
+            "insert" => Some(VirtualKeyCode::Insert),
             "home" => Some(VirtualKeyCode::Home),
             "end" => Some(VirtualKeyCode::End),
             "pageup" => Some(VirtualKeyCode::PageUp),
             "pagedown" => Some(VirtualKeyCode::PageDown),
+            "f1" => Some(VirtualKeyCode::F1),
+            "f2" => Some(VirtualKeyCode::F2),
+            "f3" => Some(VirtualKeyCode::F3),
+            "f4" => Some(VirtualKeyCode::F4),
+            "f5" => Some(VirtualKeyCode::F5),
+            "f6" => Some(VirtualKeyCode::F6),
+            "f7" => Some(VirtualKeyCode::F7),
+            "f8" => Some(VirtualKeyCode::F8),
+            "f9" => Some(VirtualKeyCode::F9),
+            "f10" => Some(VirtualKeyCode::F10),
+            "f11" => Some(VirtualKeyCode::F11),
+            "f12" => Some(VirtualKeyCode::F12),
+            "0" | "key0" => Some(VirtualKeyCode::Key0),
+            "1" | "key1" => Some(VirtualKeyCode::Key1),
+            "2" | "key2" => Some(VirtualKeyCode::Key2),
+            "3" | "key3" => Some(VirtualKeyCode::Key3),
+            "4" | "key4" => Some(VirtualKeyCode::Key4),
+            "5" | "key5" => Some(VirtualKeyCode::Key5),
+            "6" | "key6" => Some(VirtualKeyCode::Key6),
+            "7" | "key7" => Some(VirtualKeyCode::Key7),
+            "8" | "key8" => Some(VirtualKeyCode::Key8),
+            "9" | "key9" => Some(VirtualKeyCode::Key9),
+            "minus" | "-" => Some(VirtualKeyCode::Minus),
+            "equals" | "=" => Some(VirtualKeyCode::Equals),
+            "plus" | "+" => Some(VirtualKeyCode::Plus),
+            "numpadadd" => Some(VirtualKeyCode::NumpadAdd),
+            "numpadsubtract" => Some(VirtualKeyCode::NumpadSubtract),
+            "lbracket" | "[" => Some(VirtualKeyCode::LBracket),
+            "rbracket" | "]" => Some(VirtualKeyCode::RBracket),
+            "comma" => Some(VirtualKeyCode::Comma),
+            "period" | "." => Some(VirtualKeyCode::Period),
+            "backslash" | "\\" => Some(VirtualKeyCode::Backslash),
             _ => None,
         }
     }
 
-    pub fn get_action_for_key(&self, key: VirtualKeyCode) -> Option<&str> {
-        self.keybind_map.get(&key).map(|s| s.as_str())
+    pub fn get_action_for_key(&self, context: KeyContext, key: VirtualKeyCode) -> Option<&str> {
+        self.keybind_map.get(&(context, key)).map(|s| s.as_str())
+    }
+
+    /// The folder of the triage slot a key sorts images into, if any.
+    pub fn get_triage_folder(&self, key: VirtualKeyCode) -> Option<PathBuf> {
+        self.config
+            .triage
+            .slots
+            .iter()
+            .filter(|(name, folder)| TriageSettings::is_slot(name) && !folder.is_empty())
+            .find(|(name, _)| Self::string_to_keycode(name) == Some(key))
+            .map(|(_, folder)| expand_path(folder))
+    }
+
+    /// The configured command a key runs, if any.
+    pub fn get_command(&self, key: VirtualKeyCode) -> Option<&CommandSettings> {
+        self.config
+            .commands
+            .iter()
+            .find(|command| Self::parse_keys(&command.key).contains(&key))
+    }
+
+    pub fn diagnostics(&self) -> &[ConfigDiagnostic] {
+        &self.diagnostics
     }
 
-    #[allow(dead_code)]
     pub fn get_config(&self) -> &Config {
         &self.config
     }
@@ -218,6 +1501,250 @@ impl ConfigManager {
 
 impl Default for ConfigManager {
     fn default() -> Self {
-        Self::new().expect("Failed to create config manager")
+        Self::from_config(Config::default(), Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The diagnostics for a single config file, as `line: message`.
+    fn diagnose(content: &str) -> Vec<String> {
+        let mut layers = ConfigLayers::default();
+        layers.add(Path::new("config.toml"), content);
+        layers
+            .finish()
+            .1
+            .iter()
+            .map(|d| format!("{}: {}", d.line.unwrap_or(0), d.message))
+            .collect()
+    }
+
+    #[test]
+    fn defaults_are_clean() {
+        assert!(diagnose("").is_empty());
+        let defaults = toml::to_string_pretty(&Config::default()).unwrap();
+        assert!(diagnose(&defaults).is_empty());
+    }
+
+    #[test]
+    fn unknown_keys() {
+        assert_eq!(diagnose("bogus = 1"), ["1: unknown key 'bogus'"]);
+        assert_eq!(
+            diagnose("[files]\nskip_broken = true\nbogus = 1\n"),
+            ["3: unknown key 'files.bogus'"]
+        );
+        assert_eq!(
+            diagnose("[keybinds]\nFly = \"F\"\n"),
+            ["2: unknown action 'keybinds.Fly'"]
+        );
+    }
+
+    #[test]
+    fn bad_key_name() {
+        assert_eq!(
+            diagnose("[keybinds]\nExit = \"Escape, Nope\"\n"),
+            ["2: unknown key name 'Nope' in 'Exit'"]
+        );
+    }
+
+    #[test]
+    fn duplicate_with_a_later_default() {
+        // Exit comes before ToggleMarkAll, whose default is A
+        assert_eq!(
+            diagnose("[keybinds]\nExit = \"A\"\n"),
+            ["2: key 'A' is bound to both 'Exit' and 'ToggleMarkAll'; 'Exit' keeps it and 'ToggleMarkAll' loses it"]
+        );
+    }
+
+    #[test]
+    fn duplicate_with_an_earlier_default() {
+        // ToggleMark comes before InvertMarks and has M by default
+        assert_eq!(
+            diagnose("[keybinds]\nInvertMarks = \"M\"\n"),
+            ["2: key 'M' is bound to both 'ToggleMark' and 'InvertMarks'; 'ToggleMark' keeps it and 'InvertMarks' loses it"]
+        );
+    }
+
+    #[test]
+    fn duplicate_within_one_action() {
+        assert_eq!(
+            diagnose("[keybinds]\nExit = \"Escape, Escape\"\n"),
+            ["2: key 'Escape' is listed more than once in 'Exit'"]
+        );
+    }
+
+    #[test]
+    fn version_out_of_range() {
+        assert_eq!(
+            diagnose("version = -1"),
+            ["1: config version -1 is invalid"]
+        );
+        assert_eq!(
+            diagnose("version = 99"),
+            [format!(
+                "1: config version 99 is newer than this EleViewr supports ({})",
+                CONFIG_VERSION
+            )]
+        );
+    }
+
+    #[test]
+    fn upgrade_unversioned() {
+        let upgraded = ConfigManager::upgrade_document("# mine\n[files]\nskip_broken = true\n")
+            .expect("an unversioned file is upgraded");
+        assert!(upgraded.starts_with(&format!("version = {}\n# mine\n", CONFIG_VERSION)));
+        assert!(upgraded.contains("skip_broken = true"));
+    }
+
+    #[test]
+    fn upgrade_adds_defaults_as_comments() {
+        let content = "# Mine\n[keybinds]\n# Quit with G\nExit = \"G\" # not Escape\n\n# Files next\n[files]\nskip_broken = true\n# The end\n";
+        let upgraded = ConfigManager::upgrade_document(content).unwrap();
+
+        // The user's own lines are all still there, in order
+        let mut rest = upgraded.as_str();
+        for line in content.lines().filter(|line| !line.is_empty()) {
+            let at = rest.find(line).unwrap_or_else(|| panic!("lost '{}'", line));
+            rest = &rest[at + line.len()..];
+        }
+
+        // Only the version and their own settings are values
+        let values: toml::Table = upgraded.parse().unwrap();
+        assert_eq!(values["version"].as_integer(), Some(CONFIG_VERSION as i64));
+        assert_eq!(values["keybinds"].as_table().unwrap().len(), 1);
+        assert_eq!(values["files"].as_table().unwrap().len(), 1);
+        assert_eq!(diagnose(&upgraded), Vec::<String>::new());
+
+        // New entries are listed in their own tables, or under a commented
+        // header for tables the file doesn't have
+        let keybinds =
+            &upgraded[upgraded.find("[keybinds]").unwrap()..upgraded.find("[files]").unwrap()];
+        assert!(keybinds.contains("# ToggleMark = \"M\"\n"));
+        assert!(!keybinds.contains("# Exit ="));
+        let files = &upgraded[upgraded.find("[files]").unwrap()..];
+        assert!(files.contains("# sort = \"natural\"\n"));
+        assert!(!files.contains("# skip_broken"));
+        assert!(upgraded.contains("\n# [wallpaper]\n# rotation_interval = 300\n"));
+
+        // Uncommenting the new entries gives a valid file
+        let uncommented: String = upgraded
+            .lines()
+            .map(|line| match content.lines().any(|own| own == line) {
+                true => line,
+                false => line.strip_prefix("# ").unwrap_or(line),
+            })
+            .map(|line| format!("{}\n", line))
+            .collect();
+        assert!(diagnose(&uncommented).is_empty());
+        let config: Config = toml::from_str(&uncommented).unwrap();
+        assert_eq!(config.keybinds.exit, "G");
+        assert_eq!(config.wallpaper.rotation_interval, 300);
+    }
+
+    #[test]
+    fn upgrade_skips_entries_already_in_comments() {
+        let starter = ConfigManager::starter_config().unwrap();
+        let unversioned = starter.replacen(&format!("version = {}\n", CONFIG_VERSION), "", 1);
+        let upgraded = ConfigManager::upgrade_document(&unversioned).unwrap();
+        assert_eq!(
+            upgraded,
+            format!("version = {}\n{}", CONFIG_VERSION, unversioned)
+        );
+    }
+
+    #[test]
+    fn starter_config() {
+        let content = ConfigManager::starter_config().unwrap();
+        let values: toml::Table = content.parse().unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(values["version"].as_integer(), Some(CONFIG_VERSION as i64));
+        assert!(diagnose(&content).is_empty());
+
+        // Uncommenting everything gives the defaults back
+        let uncommented: String = content
+            .lines()
+            .map(|line| line.strip_prefix("# ").unwrap_or(line))
+            .filter(|line| !line.starts_with("Uncomment"))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        assert!(diagnose(&uncommented).is_empty());
+        let config: Config = toml::from_str(&uncommented).unwrap();
+        assert_eq!(config.keybinds.exit, KeyBinds::default().exit);
+    }
+
+    #[test]
+    fn upgrade_current() {
+        let current = toml::to_string_pretty(&Config::default()).unwrap();
+        assert_eq!(ConfigManager::upgrade_document(&current), None);
+    }
+
+    #[test]
+    fn upgrade_future_or_negative() {
+        for content in ["version = 99\n", "version = -1\n", "version = \"1\"\n"] {
+            assert_eq!(
+                ConfigManager::upgrade_document(content),
+                None,
+                "{}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn unwritable_user_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("file");
+        fs::write(&file, "").unwrap();
+        let system = dir.path().join("system.toml");
+        fs::write(&system, "[files]\nskip_broken = true\n").unwrap();
+
+        // The config folder can't be created inside a file
+        let user = file.join("eleviewr").join("config.toml");
+        let (config, diagnostics) =
+            ConfigManager::load_files(&ConfigSource::UserDefault, &[system, user]).unwrap();
+        assert!(config.files.skip_broken);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, None);
+        assert!(diagnostics[0]
+            .message
+            .starts_with("could not create the config file: "));
+    }
+
+    #[test]
+    fn unreadable_system_config() {
+        let dir = tempfile::tempdir().unwrap();
+        // A folder where the file should be can't be read as one
+        let system = dir.path().join("system.toml");
+        fs::create_dir(&system).unwrap();
+        let user = dir.path().join("user.toml");
+        fs::write(&user, "[files]\nskip_broken = true\n").unwrap();
+
+        let source = ConfigSource::File(user.clone());
+        let (config, diagnostics) =
+            ConfigManager::load_files(&source, &[system.clone(), user]).unwrap();
+        assert!(config.files.skip_broken);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].file, system);
+        assert!(diagnostics[0]
+            .message
+            .starts_with("could not read the config file: "));
+    }
+
+    #[test]
+    fn missing_explicit_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let user = dir.path().join("missing.toml");
+        let source = ConfigSource::File(user.clone());
+        assert!(ConfigManager::load_files(&source, &[user]).is_err());
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn same_key_in_different_contexts() {
+        // ToggleMark has M while viewing; ConfirmDelete only applies in the
+        // delete prompt
+        assert!(diagnose("[keybinds]\nConfirmDelete = \"M\"\n").is_empty());
     }
 }
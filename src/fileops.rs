@@ -0,0 +1,185 @@
+//! File operations on the images themselves: moving and copying them to
+//! another folder, and rotating them in place.
+
+use anyhow::{anyhow, Context, Result};
+use image::{DynamicImage, ImageFormat, ImageOutputFormat};
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use crate::formats::{self, Format};
+use crate::source::ImageSource;
+
+/// Quality JPEGs are saved at after rotating. They have to be encoded again,
+/// so keep as much of the original as is reasonable.
+const JPEG_QUALITY: u8 = 95;
+
+/// What to do when a file being moved or copied would replace one that is
+/// already there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collision {
+    /// Let the user pick one of the others
+    Ask,
+    Skip,
+    /// Give the new file a free name, `name_1.jpg`, `name_2.jpg`, ...
+    Rename,
+    Overwrite,
+}
+
+impl Collision {
+    /// The name used in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Collision::Ask => "ask",
+            Collision::Skip => "skip",
+            Collision::Rename => "rename",
+            Collision::Overwrite => "overwrite",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "ask" => Some(Collision::Ask),
+            "skip" => Some(Collision::Skip),
+            "rename" => Some(Collision::Rename),
+            "overwrite" => Some(Collision::Overwrite),
+            _ => None,
+        }
+    }
+}
+
+/// Where a file moved or copied into `folder` goes, keeping its name.
+pub fn target_in(path: &Path, folder: &Path) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{} has no file name", path.display()))?;
+    Ok(folder.join(name))
+}
+
+/// Moves or copies a file into `folder`, keeping its name unless it has to
+/// be renamed to avoid a collision. Returns where the file went, or `None`
+/// if it was skipped.
+pub fn transfer(
+    path: &Path,
+    folder: &Path,
+    copy: bool,
+    collision: Collision,
+) -> Result<Option<PathBuf>> {
+    let mut target = target_in(path, folder)?;
+    if target.exists() {
+        if same_file(path, &target) {
+            return Err(anyhow!("already in {}", folder.display()));
+        }
+        match collision {
+            Collision::Ask | Collision::Skip => return Ok(None),
+            Collision::Rename => target = free_name(&target),
+            Collision::Overwrite => {}
+        }
+    }
+
+    transfer_to(path, &target, copy)?;
+    Ok(Some(target))
+}
+
+/// Moves or copies a file to exactly `target`, replacing anything there.
+pub fn transfer_to(path: &Path, target: &Path, copy: bool) -> Result<()> {
+    if copy {
+        fs::copy(path, target)?;
+    } else if fs::rename(path, target).is_err() {
+        // Renaming fails across filesystems, so copy and remove instead
+        fs::copy(path, target)?;
+        if let Err(e) = fs::remove_file(path) {
+            let _ = fs::remove_file(target);
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (a.canonicalize(), b.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// The first of `name_1.ext`, `name_2.ext`, ... that isn't taken.
+fn free_name(target: &Path) -> PathBuf {
+    let stem = target.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = target
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|n| target.with_file_name(format!("{}_{}{}", stem, n, extension)))
+        .find(|candidate| !candidate.exists())
+        .unwrap_or_else(|| target.to_path_buf())
+}
+
+/// Rotates an image file by a quarter turn and saves it over the original.
+/// Only single-image formats the `image` crate can write are supported;
+/// JPEGs are encoded again, and metadata such as EXIF is not kept.
+pub fn rotate(path: &Path, clockwise: bool) -> Result<()> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let source = ImageSource::File(path.to_path_buf());
+    let format = match formats::detect_source_format(&data, &source) {
+        Some(Format::Image(
+            format @ (ImageFormat::Png
+            | ImageFormat::Jpeg
+            | ImageFormat::Bmp
+            | ImageFormat::Tiff
+            | ImageFormat::Tga
+            | ImageFormat::Qoi
+            | ImageFormat::Pnm),
+        )) => format,
+        Some(format) => return Err(anyhow!("{} images can't be rotated", format.name())),
+        None => return Err(anyhow!("Unknown image format")),
+    };
+    if formats::page_count(&data, &source) > 1 || is_apng(&data, format) {
+        return Err(anyhow!(
+            "Images with several pages or frames can't be rotated"
+        ));
+    }
+
+    let img = image::load_from_memory_with_format(&data, format)?;
+    let img = if clockwise {
+        img.rotate90()
+    } else {
+        img.rotate270()
+    };
+
+    let (img, output) = match format {
+        // The JPEG encoder only takes 8-bit gray and RGB
+        ImageFormat::Jpeg => {
+            let img = match img {
+                DynamicImage::ImageLuma8(_) | DynamicImage::ImageRgb8(_) => img,
+                img => DynamicImage::ImageRgb8(img.to_rgb8()),
+            };
+            (img, ImageOutputFormat::Jpeg(JPEG_QUALITY))
+        }
+        format => (img, format.into()),
+    };
+    let mut encoded = Cursor::new(Vec::new());
+    img.write_to(&mut encoded, output)
+        .context("Failed to encode rotated image")?;
+
+    // Write next to the original and rename over it, so a failure part way
+    // through never leaves a truncated image behind
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".rotating");
+    let temporary = path.with_file_name(name);
+    fs::write(&temporary, encoded.into_inner())
+        .and_then(|()| fs::rename(&temporary, path))
+        .inspect_err(|_| {
+            let _ = fs::remove_file(&temporary);
+        })
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Whether a PNG is animated, which decoding would flatten to one frame.
+fn is_apng(data: &[u8], format: ImageFormat) -> bool {
+    format == ImageFormat::Png
+        && image::codecs::png::PngDecoder::new(Cursor::new(data))
+            .is_ok_and(|decoder| decoder.is_apng())
+}
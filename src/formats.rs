@@ -0,0 +1,568 @@
+use anyhow::Result;
+use image::{DynamicImage, ImageFormat};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read};
+use std::path::Path;
+
+use crate::source::ImageSource;
+
+/// An image format EleViewr knows how to recognise. Formats outside the
+/// `image` crate are only decodable when their cargo feature is enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Format {
+    /// Anything the `image` crate decodes
+    Image(ImageFormat),
+    /// HEIC/HEIF, decoded with libheif (`heif` feature)
+    Heif,
+    /// AVIF, decoded with libheif (`heif` feature)
+    Avif,
+    /// JPEG XL, decoded with jxl-oxide (`jxl` feature)
+    JpegXl,
+    /// Camera RAW files, shown via their embedded preview (`raw` feature)
+    Raw,
+    /// SVG and SVGZ, rendered with resvg (`svg` feature)
+    Svg,
+}
+
+/// Extensions of the camera RAW formats. Most of these are TIFF containers,
+/// so the extension is what tells them apart from plain TIFFs.
+const RAW_EXTENSIONS: &[&str] = &[
+    "3fr", "arw", "cr2", "cr3", "crw", "dcr", "dng", "erf", "iiq", "k25", "kdc", "mef", "mos",
+    "mrw", "nef", "nrw", "orf", "pef", "raf", "rw2", "rwl", "sr2", "srf", "srw", "x3f",
+];
+
+/// Settings that affect how an image is decoded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DecodeOptions {
+    /// Demosaic RAW sensor data instead of showing the embedded preview
+    #[cfg_attr(not(feature = "raw"), allow(dead_code))]
+    pub raw_full_decode: bool,
+    /// The size in pixels to render vector images to fit within, rather
+    /// than their nominal size
+    #[cfg_attr(not(feature = "svg"), allow(dead_code))]
+    pub fit: Option<(u32, u32)>,
+    /// Which page of a multi-page file to decode, counting from 0
+    pub page: usize,
+}
+
+impl Format {
+    /// Every format this build can decode.
+    pub fn supported() -> impl Iterator<Item = Format> {
+        ImageFormat::all()
+            .filter(|format| *format != ImageFormat::Avif)
+            .map(Format::Image)
+            .chain([
+                Format::Heif,
+                Format::Avif,
+                Format::JpegXl,
+                Format::Raw,
+                Format::Svg,
+            ])
+            .filter(|format| format.is_enabled())
+    }
+
+    /// Whether this build includes a decoder for the format.
+    pub fn is_enabled(self) -> bool {
+        match self {
+            Format::Image(format) => format.reading_enabled(),
+            Format::Heif | Format::Avif => cfg!(feature = "heif"),
+            Format::JpegXl => cfg!(feature = "jxl"),
+            Format::Raw => cfg!(feature = "raw"),
+            Format::Svg => cfg!(feature = "svg"),
+        }
+    }
+
+    /// Whether the format is resolution independent, and so worth
+    /// re-rendering when the display size changes.
+    pub fn is_vector(self) -> bool {
+        self == Format::Svg
+    }
+
+    /// The name used for the format in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Image(ImageFormat::Jpeg) => "jpeg",
+            Format::Image(ImageFormat::Pnm) => "pnm",
+            Format::Image(ImageFormat::Farbfeld) => "farbfeld",
+            Format::Image(format) => format
+                .extensions_str()
+                .first()
+                .copied()
+                .unwrap_or("unknown"),
+            Format::Heif => "heic",
+            Format::Avif => "avif",
+            Format::JpegXl => "jxl",
+            Format::Raw => "raw",
+            Format::Svg => "svg",
+        }
+    }
+
+    /// The extension a file in this format is usually given.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Image(ImageFormat::Jpeg) => "jpg",
+            Format::Image(ImageFormat::Farbfeld) => "ff",
+            Format::Raw => "dng",
+            format => format.name(),
+        }
+    }
+
+    /// Looks up a format by any of its usual names or extensions, e.g.
+    /// `"jpg"`, `"jpeg"` or `"tif"`, whether or not this build can decode it.
+    pub fn from_name(name: &str) -> Option<Format> {
+        let name = name.trim().to_ascii_lowercase();
+        match name.as_str() {
+            "jfif" => Some(Format::Image(ImageFormat::Jpeg)),
+            "cur" => Some(Format::Image(ImageFormat::Ico)),
+            "pnm" => Some(Format::Image(ImageFormat::Pnm)),
+            "heic" | "heif" | "hif" => Some(Format::Heif),
+            "avif" => Some(Format::Avif),
+            "jxl" => Some(Format::JpegXl),
+            "raw" => Some(Format::Raw),
+            "svg" | "svgz" => Some(Format::Svg),
+            name if RAW_EXTENSIONS.contains(&name) => Some(Format::Raw),
+            _ => ImageFormat::from_extension(&name).map(Format::Image),
+        }
+    }
+}
+
+/// Identifies an image from its leading bytes, so extensionless or misnamed
+/// files are still found. Formats without a signature (such as TGA) fall back
+/// to the file extension.
+pub fn detect_format(header: &[u8], extension: Option<&str>) -> Option<Format> {
+    let by_extension = extension.and_then(Format::from_name);
+
+    match sniff_format(header) {
+        // TIFF-based RAWs only differ from a TIFF by their extension
+        Some(Format::Image(ImageFormat::Tiff)) if by_extension == Some(Format::Raw) => {
+            Some(Format::Raw)
+        }
+        None => by_extension,
+        format => format,
+    }
+}
+
+/// Identifies a file on disk from its first few bytes and its extension.
+pub fn detect_file_format(path: &Path) -> Option<Format> {
+    let mut header = [0; 64];
+    let len = File::open(path)
+        .and_then(|file| file.take(header.len() as u64).read(&mut header))
+        .ok()?;
+
+    detect_format(
+        &header[..len],
+        path.extension().and_then(|ext| ext.to_str()),
+    )
+}
+
+/// Identifies an image that has been read into memory.
+pub fn detect_source_format(data: &[u8], source: &ImageSource) -> Option<Format> {
+    detect_format(data, source.extension().as_deref())
+}
+
+fn sniff_format(header: &[u8]) -> Option<Format> {
+    if let Some(format) = sniff_iso_bmff(header) {
+        return Some(format);
+    }
+    // Fujifilm, Olympus and Panasonic use their own (TIFF-like) signatures
+    if header.starts_with(b"FUJIFILMCCD-RAW")
+        || header.starts_with(b"IIRO")
+        || header.starts_with(b"IIRS")
+        || header.starts_with(b"MMOR")
+        || header.starts_with(b"IIU\0")
+    {
+        return Some(Format::Raw);
+    }
+    // SVGs may open with an XML declaration, doctype or comment, so only
+    // files that get to the root element quickly are recognised by content
+    if is_svg_header(header) {
+        return Some(Format::Svg);
+    }
+    // Cursors are icons with a different type field. TGA headers often
+    // start the same way, but have no colour map where an icon has its count
+    if header.starts_with(&[0, 0, 2, 0]) && header.get(4..6).is_some_and(|count| count != [0, 0]) {
+        return Some(Format::Image(ImageFormat::Ico));
+    }
+    if header.starts_with(&[0xff, 0x0a]) || header.starts_with(b"\0\0\0\x0cJXL \x0d\x0a\x87\x0a") {
+        return Some(Format::JpegXl);
+    }
+
+    // `image::guess_format` with stricter checks for its looser signatures:
+    // `RIFF` is shared with WAV and AVI, and PNM's two-byte magic matches
+    // plenty of text files
+    match image::guess_format(header).ok()? {
+        ImageFormat::WebP if header.get(8..12) != Some(b"WEBP") => None,
+        ImageFormat::Pnm if !is_pnm_header(header) => None,
+        ImageFormat::Avif => Some(Format::Avif),
+        format => Some(Format::Image(format)),
+    }
+}
+
+/// HEIF, AVIF and Canon's CR3 are all ISO base media files, told apart by the
+/// brands in their leading `ftyp` box.
+fn sniff_iso_bmff(header: &[u8]) -> Option<Format> {
+    if header.get(4..8) != Some(b"ftyp") {
+        return None;
+    }
+
+    let box_size = u32::from_be_bytes(header.get(0..4)?.try_into().ok()?) as usize;
+    let major_brand = header.get(8..12)?;
+    // Compatible brands follow the major brand and the minor version
+    let compatible_brands = header
+        .get(16..box_size.min(header.len()))
+        .unwrap_or_default()
+        .chunks_exact(4);
+    let brands: Vec<&[u8]> = std::iter::once(major_brand)
+        .chain(compatible_brands)
+        .collect();
+
+    if major_brand == b"crx " {
+        Some(Format::Raw)
+    } else if brands
+        .iter()
+        .any(|brand| matches!(*brand, b"avif" | b"avis"))
+    {
+        Some(Format::Avif)
+    } else if brands.iter().any(|brand| {
+        matches!(
+            *brand,
+            b"heic" | b"heix" | b"heim" | b"heis" | b"hevc" | b"hevx" | b"mif1" | b"msf1"
+        )
+    }) {
+        Some(Format::Heif)
+    } else {
+        None
+    }
+}
+
+/// An SVG's root `<svg` element may only be preceded by whitespace, the XML
+/// declaration, comments and a doctype, so HTML and text that merely mention
+/// `<svg` aren't taken for one.
+fn is_svg_header(header: &[u8]) -> bool {
+    let mut rest = header.strip_prefix(b"\xef\xbb\xbf").unwrap_or(header);
+    loop {
+        rest = &rest[rest.iter().take_while(|b| b.is_ascii_whitespace()).count()..];
+        let end = if rest.starts_with(b"<?xml") {
+            b"?>".as_slice()
+        } else if rest.starts_with(b"<!--") {
+            b"-->".as_slice()
+        } else if rest.starts_with(b"<!DOCTYPE") {
+            b">".as_slice()
+        } else {
+            break;
+        };
+        let Some(start) = rest.windows(end.len()).position(|window| window == end) else {
+            return false;
+        };
+        rest = &rest[start + end.len()..];
+    }
+
+    // The element name must end there, and may be cut off by the header
+    rest.strip_prefix(b"<svg").is_some_and(|after| {
+        after
+            .first()
+            .is_none_or(|&b| b.is_ascii_whitespace() || b == b'>' || b == b'/')
+    })
+}
+
+/// PNM's magic (`P1`..`P7`) must be followed by whitespace and then the
+/// width or a comment.
+fn is_pnm_header(header: &[u8]) -> bool {
+    let mut rest = header.iter().skip(2).peekable();
+    if !rest.peek().is_some_and(|b| b.is_ascii_whitespace()) {
+        return false;
+    }
+    rest.find(|b| !b.is_ascii_whitespace())
+        .is_some_and(|&b| b.is_ascii_digit() || b == b'#')
+}
+
+/// Resolves the configured format names, ignoring any that aren't recognised
+/// or supported (those are reported by config validation).
+pub fn enabled_formats(names: &[String]) -> HashSet<Format> {
+    names
+        .iter()
+        .filter_map(|name| Format::from_name(name))
+        .filter(|format| format.is_enabled())
+        .collect()
+}
+
+/// The number of pages in a multi-page file (TIFF pages, icon sizes, HEIF
+/// image collections); 1 for everything else.
+pub fn page_count(data: &[u8], source: &ImageSource) -> usize {
+    match detect_source_format(data, source) {
+        Some(Format::Image(ImageFormat::Tiff)) => crate::pages::tiff_page_count(data),
+        Some(Format::Image(ImageFormat::Ico)) => crate::pages::icon_page_count(data),
+        Some(Format::Heif | Format::Avif) => heif_page_count(data),
+        _ => 1,
+    }
+}
+
+/// Reads an image's size from its header without decoding it, for the
+/// formats the `image` crate handles.
+pub fn image_dimensions(source: &ImageSource) -> Option<(u32, u32)> {
+    match source {
+        ImageSource::File(path) => match detect_file_format(path)? {
+            Format::Image(format) => {
+                let file = BufReader::new(File::open(path).ok()?);
+                image::io::Reader::with_format(file, format)
+                    .into_dimensions()
+                    .ok()
+            }
+            _ => None,
+        },
+        ImageSource::ArchiveEntry { .. } | ImageSource::Memory(_) => {
+            let data = source.read().ok()?;
+            match detect_source_format(&data, source)? {
+                Format::Image(format) => image::io::Reader::with_format(Cursor::new(data), format)
+                    .into_dimensions()
+                    .ok(),
+                _ => None,
+            }
+        }
+    }
+}
+
+/// Decodes an image with the decoder for its detected format. Formats
+/// without a signature are left to the `image` crate to identify.
+pub fn decode_image(
+    data: &[u8],
+    source: &ImageSource,
+    options: DecodeOptions,
+) -> Result<DynamicImage> {
+    match detect_source_format(data, source) {
+        Some(Format::Image(ImageFormat::Tiff)) if options.page > 0 => {
+            crate::pages::decode_tiff_page(data, options.page)
+        }
+        Some(Format::Image(ImageFormat::Ico)) if options.page > 0 => {
+            crate::pages::decode_icon_page(data, options.page)
+        }
+        Some(format @ (Format::Heif | Format::Avif)) => decode_heif(data, format, options.page),
+        Some(Format::JpegXl) => decode_jxl(data),
+        Some(Format::Raw) => decode_raw(data, options),
+        Some(Format::Svg) => decode_svg(data, source, options),
+        // Use the sniffed format, which covers some the `image` crate
+        // can't guess itself (such as cursors)
+        Some(Format::Image(format)) => Ok(image::load_from_memory_with_format(data, format)?),
+        None => Ok(image::load_from_memory(data)?),
+    }
+}
+
+#[cfg(feature = "heif")]
+fn decode_heif(data: &[u8], _format: Format, page: usize) -> Result<DynamicImage> {
+    crate::heif::decode(data, page)
+}
+
+#[cfg(not(feature = "heif"))]
+fn decode_heif(_data: &[u8], format: Format, _page: usize) -> Result<DynamicImage> {
+    Err(not_enabled(format, "heif"))
+}
+
+#[cfg(feature = "heif")]
+fn heif_page_count(data: &[u8]) -> usize {
+    crate::heif::page_count(data)
+}
+
+#[cfg(not(feature = "heif"))]
+fn heif_page_count(_data: &[u8]) -> usize {
+    1
+}
+
+#[cfg(feature = "jxl")]
+fn decode_jxl(data: &[u8]) -> Result<DynamicImage> {
+    crate::jxl::decode(data)
+}
+
+#[cfg(not(feature = "jxl"))]
+fn decode_jxl(_data: &[u8]) -> Result<DynamicImage> {
+    Err(not_enabled(Format::JpegXl, "jxl"))
+}
+
+#[cfg(feature = "raw")]
+fn decode_raw(data: &[u8], options: DecodeOptions) -> Result<DynamicImage> {
+    crate::raw::decode(data, options.raw_full_decode)
+}
+
+#[cfg(not(feature = "raw"))]
+fn decode_raw(_data: &[u8], _options: DecodeOptions) -> Result<DynamicImage> {
+    Err(not_enabled(Format::Raw, "raw"))
+}
+
+#[cfg(feature = "svg")]
+fn decode_svg(data: &[u8], source: &ImageSource, options: DecodeOptions) -> Result<DynamicImage> {
+    // Relative references (e.g. linked bitmaps) resolve next to the file
+    let resources_dir = source.path().and_then(Path::parent);
+    crate::svg::decode(data, resources_dir, options.fit)
+}
+
+#[cfg(not(feature = "svg"))]
+fn decode_svg(
+    _data: &[u8],
+    _source: &ImageSource,
+    _options: DecodeOptions,
+) -> Result<DynamicImage> {
+    Err(not_enabled(Format::Svg, "svg"))
+}
+
+#[cfg(not(all(feature = "heif", feature = "jxl", feature = "raw", feature = "svg")))]
+fn not_enabled(format: Format, feature: &str) -> anyhow::Error {
+    anyhow::anyhow!(
+        "{} support is not enabled in this build (rebuild with `--features {}`)",
+        format.name().to_uppercase(),
+        feature
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_by_content() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+        assert_eq!(
+            detect_format(png, Some("jpg")),
+            Some(Format::Image(ImageFormat::Png))
+        );
+        assert_eq!(
+            detect_format(b"\xff\xd8\xff\xe0\0\x10JFIF", None),
+            Some(Format::Image(ImageFormat::Jpeg))
+        );
+        assert_eq!(
+            detect_format(b"RIFF\x24\0\0\0WEBPVP8 ", None),
+            Some(Format::Image(ImageFormat::WebP))
+        );
+        // A WAV file shares WebP's RIFF signature
+        assert_eq!(detect_format(b"RIFF\x24\0\0\0WAVEfmt ", None), None);
+    }
+
+    #[test]
+    fn falls_back_to_the_extension() {
+        assert_eq!(
+            detect_format(b"\0\0\x0a\0\0\0\0\0", Some("tga")),
+            Some(Format::Image(ImageFormat::Tga))
+        );
+        assert_eq!(detect_format(b"plain text", None), None);
+        assert_eq!(detect_format(b"plain text", Some("txt")), None);
+    }
+
+    #[test]
+    fn tiff_based_raw() {
+        let tiff = b"II*\0\x08\0\0\0";
+        assert_eq!(detect_format(tiff, Some("NEF")), Some(Format::Raw));
+        assert_eq!(
+            detect_format(tiff, Some("tif")),
+            Some(Format::Image(ImageFormat::Tiff))
+        );
+    }
+
+    #[test]
+    fn short_input() {
+        for header in [
+            &b""[..],
+            b"\x89",
+            b"\xff",
+            b"II",
+            b"P6",
+            b"\0\0\x02\0",
+            b"<sv",
+        ] {
+            assert_eq!(detect_format(header, None), None, "{:?}", header);
+        }
+    }
+
+    #[test]
+    fn svg() {
+        for header in [
+            &b"<svg xmlns=\"http://www.w3.org/2000/svg\">"[..],
+            b"\n  <svg>",
+            b"\xef\xbb\xbf<svg>",
+            b"<?xml version=\"1.0\"?>\n<!-- drawn by hand -->\n<svg ",
+            b"<!DOCTYPE svg>\n<svg/>",
+            b"<?xml version=\"1.0\"?><svg",
+        ] {
+            assert_eq!(
+                detect_format(header, None),
+                Some(Format::Svg),
+                "{:?}",
+                header
+            );
+        }
+        for header in [
+            &b"<!DOCTYPE html><html><body><svg>"[..],
+            b"Draw it with <svg> elements",
+            b"<svgfont>",
+            b"<?xml version=\"1.0\"?><rss>",
+            b"<?xml version=\"1.0\" encoding",
+        ] {
+            assert_eq!(detect_format(header, None), None, "{:?}", header);
+        }
+        // An SVG whose prolog doesn't fit in the header is still found by name
+        assert_eq!(
+            detect_format(b"<?xml version=\"1.0\" encoding", Some("svg")),
+            Some(Format::Svg)
+        );
+    }
+
+    /// An `ftyp` box with the given major and compatible brands.
+    fn ftyp(major: &[u8; 4], compatible: &[&[u8; 4]]) -> Vec<u8> {
+        let size = 16 + 4 * compatible.len() as u32;
+        let mut header = size.to_be_bytes().to_vec();
+        header.extend_from_slice(b"ftyp");
+        header.extend_from_slice(major);
+        header.extend_from_slice(&[0; 4]);
+        for brand in compatible {
+            header.extend_from_slice(*brand);
+        }
+        header
+    }
+
+    #[test]
+    fn iso_bmff() {
+        assert_eq!(
+            sniff_iso_bmff(&ftyp(b"avif", &[b"mif1"])),
+            Some(Format::Avif)
+        );
+        assert_eq!(
+            sniff_iso_bmff(&ftyp(b"mif1", &[b"avif"])),
+            Some(Format::Avif)
+        );
+        assert_eq!(
+            sniff_iso_bmff(&ftyp(b"heic", &[b"mif1"])),
+            Some(Format::Heif)
+        );
+        assert_eq!(sniff_iso_bmff(&ftyp(b"msf1", &[])), Some(Format::Heif));
+        assert_eq!(
+            sniff_iso_bmff(&ftyp(b"crx ", &[b"isom"])),
+            Some(Format::Raw)
+        );
+        // MP4 video
+        assert_eq!(sniff_iso_bmff(&ftyp(b"isom", &[b"mp41"])), None);
+    }
+
+    #[test]
+    fn iso_bmff_truncated() {
+        let header = ftyp(b"mif1", &[b"heic"]);
+        for len in 0..12 {
+            assert_eq!(sniff_iso_bmff(&header[..len]), None, "{} bytes", len);
+        }
+        // The major brand alone is enough
+        assert_eq!(sniff_iso_bmff(&header[..12]), Some(Format::Heif));
+        // Compatible brands cut off by the header are not read past its end
+        let header = ftyp(b"isom", &[b"mp41", b"avif"]);
+        assert_eq!(sniff_iso_bmff(&header[..22]), None);
+        assert_eq!(sniff_iso_bmff(&header), Some(Format::Avif));
+    }
+
+    #[test]
+    fn pnm_header() {
+        assert!(is_pnm_header(b"P6 640 480 255\n"));
+        assert!(is_pnm_header(b"P3\n# made by hand\n2 2\n"));
+        assert!(is_pnm_header(b"P1\r\n  5 5"));
+        assert!(!is_pnm_header(b"P6x"));
+        assert!(!is_pnm_header(b"P1 is the first point"));
+        assert!(!is_pnm_header(b"P6"));
+        assert!(!is_pnm_header(b"P6   "));
+        assert!(!is_pnm_header(b""));
+    }
+}
@@ -0,0 +1,97 @@
+//! HEIC/HEIF and AVIF decoding through the system libheif.
+
+use anyhow::{anyhow, Context, Result};
+use image::{DynamicImage, ImageBuffer, Rgba};
+use libheif_rs::{ColorSpace, HeifContext, ImageHandle, LibHeif, RgbChroma};
+
+fn open(data: &[u8]) -> Result<HeifContext<'_>> {
+    HeifContext::read_from_bytes(data).context("Failed to read HEIF file")
+}
+
+/// The number of top-level images in the file, e.g. the shots of a burst.
+pub fn page_count(data: &[u8]) -> usize {
+    open(data)
+        .map(|context| context.image_ids().len())
+        .unwrap_or(1)
+        .max(1)
+}
+
+/// One of the file's top-level images, where page 0 is the primary image
+/// and the rest follow in file order.
+fn image_handle(context: &HeifContext, page: usize) -> Result<ImageHandle> {
+    let primary = context
+        .primary_image_handle()
+        .context("HEIF file has no primary image")?;
+    if page == 0 {
+        return Ok(primary);
+    }
+    context
+        .top_level_image_handles()
+        .into_iter()
+        .filter(|handle| handle.item_id() != primary.item_id())
+        .nth(page - 1)
+        .ok_or_else(|| anyhow!("HEIF file has no image {}", page + 1))
+}
+
+/// The raw ICC profile of one of the file's images, if it has one. Each
+/// image of a collection may have its own.
+#[cfg(feature = "icc")]
+pub fn icc_profile(data: &[u8], page: usize) -> Option<Vec<u8>> {
+    let context = open(data).ok()?;
+    let handle = image_handle(&context, page).ok()?;
+    handle.color_profile_raw().map(|profile| profile.data)
+}
+
+/// Decodes one of the file's top-level images (see `image_handle`). 10- and
+/// 12-bit content is kept at 16 bits per channel. libheif applies the
+/// rotation and mirroring for us.
+pub fn decode(data: &[u8], page: usize) -> Result<DynamicImage> {
+    let lib_heif = LibHeif::new();
+    let context = open(data)?;
+    let handle = image_handle(&context, page)?;
+
+    let high_bit_depth = handle.luma_bits_per_pixel() > 8;
+    let chroma = if high_bit_depth {
+        RgbChroma::HdrRgbaLe
+    } else {
+        RgbChroma::Rgba
+    };
+    let image = lib_heif
+        .decode(&handle, ColorSpace::Rgb(chroma), None)
+        .context("Failed to decode HEIF image")?;
+
+    let plane = image
+        .planes()
+        .interleaved
+        .ok_or_else(|| anyhow!("libheif returned no interleaved plane"))?;
+    let (width, height) = (plane.width, plane.height);
+
+    if !high_bit_depth {
+        let row_bytes = width as usize * 4;
+        let pixels = plane
+            .data
+            .chunks(plane.stride)
+            .take(height as usize)
+            .flat_map(|row| &row[..row_bytes])
+            .copied()
+            .collect();
+        let buffer = ImageBuffer::<Rgba<u8>, _>::from_raw(width, height, pixels)
+            .ok_or_else(|| anyhow!("HEIF image data is truncated"))?;
+        return Ok(DynamicImage::ImageRgba8(buffer));
+    }
+
+    // Samples are little-endian and only use `bits_per_pixel` bits; widen
+    // them to the full 16-bit range
+    let shift = 16u32.saturating_sub(plane.bits_per_pixel as u32);
+    let row_bytes = width as usize * 8;
+    let pixels = plane
+        .data
+        .chunks(plane.stride)
+        .take(height as usize)
+        .flat_map(|row| row[..row_bytes].chunks_exact(2))
+        .map(|sample| u16::from_le_bytes([sample[0], sample[1]]) << shift)
+        .collect();
+    let buffer = ImageBuffer::<Rgba<u16>, _>::from_raw(width, height, pixels)
+        .ok_or_else(|| anyhow!("HEIF image data is truncated"))?;
+    Ok(DynamicImage::ImageRgba16(buffer))
+}
@@ -0,0 +1,409 @@
+//! The control socket: a running viewer listens on a Unix socket for JSON
+//! requests, one per line, so it can be driven from compositor bindings and
+//! scripts with `eleviewr msg`.
+
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
+
+/// How long a request may wait for the viewer to get round to it.
+const REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    Next,
+    Prev,
+    /// Shows the image at a position in the list, counting from 1
+    Goto {
+        index: usize,
+    },
+    /// Opens a file, folder or archive, as if given on the command line
+    Open {
+        path: PathBuf,
+    },
+    Zoom {
+        level: Zoom,
+    },
+    Quit,
+    SetWallpaper,
+    /// Runs any keybinding action by its config name, such as `ToggleMark`
+    Action {
+        name: String,
+    },
+    /// Queries the current image's path
+    Path,
+    /// Queries the current image's position in the list
+    Index,
+    /// Queries the marked images
+    Marks,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Zoom {
+    /// A zoom factor, where 1 fits the image to the window
+    Factor(f32),
+    Step(ZoomStep),
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ZoomStep {
+    In,
+    Out,
+    Reset,
+}
+
+/// The answer to a request. Queries fill in the fields they ask for.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Response {
+    pub ok: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub count: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub marks: Option<Vec<PathBuf>>,
+}
+
+impl Response {
+    pub fn ok() -> Self {
+        Self {
+            ok: true,
+            ..Self::default()
+        }
+    }
+
+    pub fn error(error: &anyhow::Error) -> Self {
+        Self {
+            error: Some(format!("{:#}", error)),
+            ..Self::default()
+        }
+    }
+}
+
+/// Where the viewer listens: in a folder of its own under
+/// `$XDG_RUNTIME_DIR`, which only the user can get into. There is no
+/// fallback to a shared folder such as /tmp, where other users could get at
+/// the socket or put one of their own in its place.
+pub fn socket_path() -> Result<PathBuf> {
+    let dir = runtime_dir()
+        .ok_or_else(|| anyhow!("$XDG_RUNTIME_DIR is not set, so remote control is off"))?;
+    Ok(dir.join("control.sock"))
+}
+
+/// EleViewr's folder under `$XDG_RUNTIME_DIR`, for files that only the
+/// user may touch, or `None` if there is no runtime folder.
+pub(crate) fn runtime_dir() -> Option<PathBuf> {
+    dirs::runtime_dir().map(|dir| dir.join("eleviewr"))
+}
+
+/// Creates the socket's folder, readable by the user alone, or checks that
+/// the one already there belongs to the user running EleViewr and is closed
+/// to everyone else. The socket is bound inside it, so it is never reachable
+/// by anyone else, even before its own permissions are set.
+pub(crate) fn private_dir(dir: &Path) -> Result<()> {
+    if let Err(e) = fs::DirBuilder::new().mode(0o700).create(dir) {
+        if e.kind() != std::io::ErrorKind::AlreadyExists {
+            return Err(e).with_context(|| format!("Failed to create {}", dir.display()));
+        }
+    }
+    let metadata = fs::symlink_metadata(dir)?;
+    // SAFETY: geteuid has no preconditions and can't fail
+    let user = unsafe { libc::geteuid() };
+    let private =
+        metadata.is_dir() && metadata.uid() == user && metadata.permissions().mode() & 0o077 == 0;
+    if !private {
+        return Err(anyhow!(
+            "Not using {}, as others can get into it",
+            dir.display()
+        ));
+    }
+    Ok(())
+}
+
+/// A request waiting for the viewer, with the way back to its sender.
+pub type Pending = (Request, mpsc::Sender<Response>);
+
+/// The listening end. The most recently started viewer takes the socket
+/// over, and it is removed on exit unless another viewer has since.
+pub struct Server {
+    path: PathBuf,
+    /// The socket file's device and inode, to tell it apart from a newer
+    /// viewer's
+    id: (u64, u64),
+    requests: mpsc::Receiver<Pending>,
+}
+
+impl Server {
+    pub fn start() -> Result<Self> {
+        let path = socket_path()?;
+        private_dir(path.parent().unwrap_or(Path::new("/")))?;
+        // Either left behind by a viewer that crashed, or one that is still
+        // running and hands the socket over
+        let stale =
+            fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_socket());
+        if stale {
+            fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("Failed to listen on {}", path.display()))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))?;
+        let metadata = fs::metadata(&path)?;
+
+        let (sender, requests) = mpsc::channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let sender = sender.clone();
+                std::thread::spawn(move || serve(stream, sender));
+            }
+        });
+
+        Ok(Self {
+            path,
+            id: (metadata.dev(), metadata.ino()),
+            requests,
+        })
+    }
+
+    /// The next request that has come in, if any.
+    pub fn try_recv(&self) -> Option<Pending> {
+        self.requests.try_recv().ok()
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let ours = fs::metadata(&self.path)
+            .is_ok_and(|metadata| (metadata.dev(), metadata.ino()) == self.id);
+        if ours {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Answers the requests on one connection until the client hangs up.
+fn serve(stream: UnixStream, sender: mpsc::Sender<Pending>) {
+    let Ok(mut writer) = stream.try_clone() else {
+        return;
+    };
+    for line in BufReader::new(stream).lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let response = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let (reply, response) = mpsc::channel();
+                if sender.send((request, reply)).is_err() {
+                    return;
+                }
+                response
+                    .recv_timeout(REPLY_TIMEOUT)
+                    .unwrap_or_else(|_| Response::error(&anyhow!("The viewer did not answer")))
+            }
+            Err(e) => Response::error(&anyhow!("Invalid request: {}", e)),
+        };
+        let Ok(mut json) = serde_json::to_string(&response) else {
+            return;
+        };
+        json.push('\n');
+        if writer.write_all(json.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+/// Sends one request to the running viewer and waits for its answer.
+pub fn send(request: &Request) -> Result<Response> {
+    let path = socket_path()?;
+    let mut stream = UnixStream::connect(&path)
+        .with_context(|| format!("No EleViewr is listening on {}", path.display()))?;
+    let mut json = serde_json::to_string(request)?;
+    json.push('\n');
+    stream.write_all(json.as_bytes())?;
+
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    if line.is_empty() {
+        return Err(anyhow!("EleViewr closed the connection without answering"));
+    }
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Turns the arguments of `eleviewr msg` into a request.
+pub fn parse_message(command: &str, args: &[String]) -> Result<Request> {
+    let arg = |name: &str| {
+        args.first()
+            .ok_or_else(|| anyhow!("'{}' needs {}", command, name))
+    };
+    let request = match command {
+        "next" => Request::Next,
+        "prev" | "previous" => Request::Prev,
+        "goto" => {
+            let index = arg("an index")?;
+            Request::Goto {
+                index: index
+                    .parse()
+                    .map_err(|_| anyhow!("'{}' is not an index", index))?,
+            }
+        }
+        "open" => {
+            let path = Path::new(arg("a path")?);
+            // The viewer may have been started somewhere else
+            Request::Open {
+                path: std::path::absolute(path)?,
+            }
+        }
+        "zoom" => {
+            let level = arg("in, out, reset or a zoom factor")?;
+            Request::Zoom {
+                level: match level.as_str() {
+                    "in" => Zoom::Step(ZoomStep::In),
+                    "out" => Zoom::Step(ZoomStep::Out),
+                    "reset" => Zoom::Step(ZoomStep::Reset),
+                    factor => Zoom::Factor(
+                        factor
+                            .parse()
+                            .map_err(|_| anyhow!("'{}' is not a zoom level", factor))?,
+                    ),
+                },
+            }
+        }
+        "quit" => Request::Quit,
+        "set-wallpaper" => Request::SetWallpaper,
+        "action" => Request::Action {
+            name: arg("an action name")?.clone(),
+        },
+        "path" => Request::Path,
+        "index" => Request::Index,
+        "marks" => Request::Marks,
+        _ => return Err(anyhow!("Unknown command '{}'", command)),
+    };
+    Ok(request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(message: &str) -> Result<Request> {
+        let mut words = message.split(' ');
+        let command = words.next().unwrap();
+        let args: Vec<String> = words.map(str::to_string).collect();
+        parse_message(command, &args)
+    }
+
+    /// The request as it goes over the socket.
+    fn json(message: &str) -> String {
+        serde_json::to_string(&parse(message).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn commands() {
+        assert_eq!(json("next"), r#"{"command":"next"}"#);
+        assert_eq!(json("prev"), r#"{"command":"prev"}"#);
+        assert_eq!(json("previous"), r#"{"command":"prev"}"#);
+        assert_eq!(json("goto 12"), r#"{"command":"goto","index":12}"#);
+        assert_eq!(json("open /pics"), r#"{"command":"open","path":"/pics"}"#);
+        assert_eq!(json("zoom in"), r#"{"command":"zoom","level":"in"}"#);
+        assert_eq!(json("zoom out"), r#"{"command":"zoom","level":"out"}"#);
+        assert_eq!(json("zoom reset"), r#"{"command":"zoom","level":"reset"}"#);
+        assert_eq!(json("zoom 2.5"), r#"{"command":"zoom","level":2.5}"#);
+        assert_eq!(json("quit"), r#"{"command":"quit"}"#);
+        assert_eq!(json("set-wallpaper"), r#"{"command":"set-wallpaper"}"#);
+        assert_eq!(
+            json("action ToggleMark"),
+            r#"{"command":"action","name":"ToggleMark"}"#
+        );
+        assert_eq!(json("path"), r#"{"command":"path"}"#);
+        assert_eq!(json("index"), r#"{"command":"index"}"#);
+        assert_eq!(json("marks"), r#"{"command":"marks"}"#);
+    }
+
+    #[test]
+    fn open_makes_the_path_absolute() {
+        let Request::Open { path } = parse("open pics").unwrap() else {
+            panic!("not an open request");
+        };
+        assert_eq!(path, std::env::current_dir().unwrap().join("pics"));
+    }
+
+    #[test]
+    fn bad_arguments() {
+        let error = |message: &str| parse(message).unwrap_err().to_string();
+        assert_eq!(error("goto"), "'goto' needs an index");
+        assert_eq!(error("goto x"), "'x' is not an index");
+        assert_eq!(error("goto -1"), "'-1' is not an index");
+        assert_eq!(error("zoom big"), "'big' is not a zoom level");
+        assert_eq!(error("action"), "'action' needs an action name");
+        assert_eq!(error("jump"), "Unknown command 'jump'");
+    }
+
+    #[test]
+    fn requests_from_json() {
+        let request = |line: &str| serde_json::from_str::<Request>(line);
+        assert!(matches!(
+            request(r#"{"command": "goto", "index": 3}"#),
+            Ok(Request::Goto { index: 3 })
+        ));
+        assert!(matches!(
+            request(r#"{"command": "zoom", "level": 2}"#),
+            Ok(Request::Zoom {
+                level: Zoom::Factor(_)
+            })
+        ));
+        assert!(request(r#"{"command": "jump"}"#).is_err());
+        assert!(request(r#"{"command": "goto"}"#).is_err());
+        assert!(request(r#"{"command": "goto", "index": "3"}"#).is_err());
+        assert!(request(r#"{"index": 3}"#).is_err());
+        assert!(request("next").is_err());
+    }
+
+    #[test]
+    fn socket_folder_is_private() {
+        let runtime = tempfile::tempdir().unwrap();
+        let dir = runtime.path().join("eleviewr");
+        private_dir(&dir).unwrap();
+        assert_eq!(
+            fs::metadata(&dir).unwrap().permissions().mode() & 0o777,
+            0o700
+        );
+        // Already there and still private
+        private_dir(&dir).unwrap();
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(private_dir(&dir).is_err());
+
+        let link = runtime.path().join("link");
+        std::os::unix::fs::symlink(runtime.path(), &link).unwrap();
+        assert!(private_dir(&link).is_err());
+
+        // Another user's folder is turned down, even inside one of theirs
+        // (only root can set that up)
+        if unsafe { libc::geteuid() } == 0 {
+            let theirs = runtime.path().join("theirs");
+            fs::create_dir(&theirs).unwrap();
+            let dir = theirs.join("eleviewr");
+            fs::DirBuilder::new().mode(0o700).create(&dir).unwrap();
+            for path in [&theirs, &dir] {
+                std::os::unix::fs::chown(path, Some(65534), Some(65534)).unwrap();
+            }
+            assert!(private_dir(&dir).is_err());
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! JPEG XL decoding with the pure-Rust jxl-oxide decoder.
+
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, ImageBuffer, Rgba};
+use jxl_oxide::image::BitDepth;
+use jxl_oxide::{EnumColourEncoding, JxlImage, RenderingIntent};
+use std::io::Cursor;
+
+/// The brightness of SDR white in a PQ image (ITU-R BT.2408), which becomes
+/// 1.0 in linear light so highlights go above it.
+const REFERENCE_WHITE_NITS: f32 = 203.0;
+
+/// Converts linear BT.2020 RGB to linear sRGB (BT.709) primaries.
+const BT2020_TO_BT709: [[f32; 3]; 3] = [
+    [1.6605, -0.5876, -0.0728],
+    [-0.1246, 1.1329, -0.0083],
+    [-0.0182, -0.1006, 1.1187],
+];
+
+/// Decodes the first frame. SDR images with integer samples come out as 16
+/// bits per channel in their own (usually sRGB) encoding, which holds up to
+/// 16-bit content exactly. HDR (PQ or HLG) images and images with float
+/// samples are rendered to linear light and kept as floats, as EXR is, so
+/// values above 1.0 survive for the renderer to tone map. jxl-oxide applies
+/// the image's orientation while rendering.
+pub fn decode(data: &[u8]) -> Result<DynamicImage> {
+    let mut image = JxlImage::builder()
+        .read(Cursor::new(data))
+        .map_err(|e| anyhow!("Failed to read JPEG XL file: {}", e))?;
+
+    let hdr = image.hdr_type().is_some();
+    let float = hdr
+        || matches!(
+            image.image_header().metadata.bit_depth,
+            BitDepth::FloatSample { .. }
+        );
+    if hdr {
+        // Rendering to anything but PQ or HLG would have jxl-oxide tone map
+        // the highlights down to SDR
+        image.request_color_encoding(EnumColourEncoding::bt2100_pq(RenderingIntent::Relative));
+    } else if float {
+        image.request_color_encoding(EnumColourEncoding::srgb_linear(RenderingIntent::Relative));
+    }
+
+    let render = image
+        .render_frame(0)
+        .map_err(|e| anyhow!("Failed to decode JPEG XL image: {}", e))?;
+
+    let mut stream = render.stream();
+    let (width, height, channels) = (stream.width(), stream.height(), stream.channels());
+    let mut samples = vec![0f32; width as usize * height as usize * channels as usize];
+    stream.write_to_buffer(&mut samples);
+
+    // Expand whatever channel layout we got to RGBA
+    let mut pixels: Vec<f32> = match channels {
+        1 => samples.iter().flat_map(|&l| [l, l, l, 1.0]).collect(),
+        2 => samples
+            .chunks_exact(2)
+            .flat_map(|la| [la[0], la[0], la[0], la[1]])
+            .collect(),
+        3 => samples
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 1.0])
+            .collect(),
+        4 => samples,
+        _ => {
+            return Err(anyhow!(
+                "Unsupported JPEG XL channel layout ({channels} channels)"
+            ))
+        }
+    };
+
+    if !float {
+        let pixels = pixels
+            .into_iter()
+            .map(|sample| (sample.clamp(0.0, 1.0) * 65535.0).round() as u16)
+            .collect();
+        let buffer = ImageBuffer::<Rgba<u16>, _>::from_raw(width, height, pixels)
+            .ok_or_else(|| anyhow!("JPEG XL image data is truncated"))?;
+        return Ok(DynamicImage::ImageRgba16(buffer));
+    }
+
+    if hdr {
+        for pixel in pixels.chunks_exact_mut(4) {
+            let rgb = [pixel[0], pixel[1], pixel[2]].map(pq_to_linear);
+            for (channel, row) in BT2020_TO_BT709.iter().enumerate() {
+                pixel[channel] = row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2];
+            }
+        }
+    }
+    // Colours outside the sRGB gamut come out negative
+    for pixel in pixels.chunks_exact_mut(4) {
+        for sample in &mut pixel[..3] {
+            *sample = sample.max(0.0);
+        }
+    }
+    let buffer = ImageBuffer::<Rgba<f32>, _>::from_raw(width, height, pixels)
+        .ok_or_else(|| anyhow!("JPEG XL image data is truncated"))?;
+    Ok(DynamicImage::ImageRgba32F(buffer))
+}
+
+/// The PQ (SMPTE ST 2084) EOTF, scaled so that SDR white is 1.0.
+fn pq_to_linear(encoded: f32) -> f32 {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+    let e = encoded.clamp(0.0, 1.0).powf(1.0 / M2);
+    let nits = ((e - C1).max(0.0) / (C2 - C3 * e)).powf(1.0 / M1) * 10000.0;
+    nits / REFERENCE_WHITE_NITS
+}
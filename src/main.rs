@@ -1,62 +1,355 @@
 use anyhow::{anyhow, Result};
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use image::GenericImageView;
-use std::collections::VecDeque;
+use notify::event::{AccessKind, AccessMode, ModifyKind, RenameMode};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use std::sync::{Arc, Mutex};
+use std::sync::{mpsc, Arc, Mutex};
 use walkdir::WalkDir;
-use wgpu::util::DeviceExt;
 use winit::{
-    event::{ElementState, Event, WindowEvent},
+    event::{ElementState, Event, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::WindowBuilder,
 };
 
+mod clipboard;
+mod color;
+mod commands;
 mod config;
+mod fileops;
+mod formats;
+#[cfg(feature = "heif")]
+mod heif;
+mod ipc;
+#[cfg(feature = "jxl")]
+mod jxl;
 mod notifications;
-use config::ConfigManager;
+mod pages;
+#[cfg(feature = "raw")]
+mod raw;
+mod rename;
+mod sort;
+mod source;
+#[cfg(feature = "svg")]
+mod svg;
+mod wallpaper;
+use config::{ConfigManager, ConfigSource, KeyContext};
+use fileops::Collision;
+use formats::Format;
 use notifications::{NotificationManager, NotificationType};
+use sort::SortMode;
+use source::{ImageSource, MemoryImage};
 
 #[derive(Parser, Debug)]
 #[command(name = "eleviewr")]
 #[command(author = "User")]
 #[command(version = "0.5.0")]
 #[command(about = "A lightweight image viewer for Wayland/Hyprland", long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
-    #[arg(help = "Image file to open (optional, defaults to current directory)")]
-    image_path: Option<String>,
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+
+    #[arg(
+        value_name = "PATH",
+        help = "Image files, directories, ZIP/CBZ/TAR archives or glob patterns to open, or - to read image data or a list of paths from stdin (defaults to the current directory)"
+    )]
+    paths: Vec<String>,
+
+    #[arg(
+        long,
+        value_name = "FILE",
+        help = "Read a newline- or NUL-separated list of paths from FILE (- for stdin)"
+    )]
+    files_from: Option<PathBuf>,
+
+    #[arg(short, long, help = "Include images in sub-folders")]
+    recursive: bool,
+
+    #[arg(
+        long,
+        value_name = "N",
+        help = "Include at most N levels of sub-folders (implies --recursive)"
+    )]
+    max_depth: Option<usize>,
+
+    #[arg(
+        short,
+        long,
+        help = "Jump to new images as they appear, e.g. in a screenshot folder"
+    )]
+    follow: bool,
+
+    #[arg(
+        long,
+        visible_alias = "output-marked",
+        help = "Print the paths of marked images on exit; exits with 1 if none were marked"
+    )]
+    pick: bool,
+
+    #[arg(
+        short = '0',
+        long,
+        requires = "pick",
+        help = "Separate the printed paths with NUL bytes instead of newlines"
+    )]
+    null: bool,
+
+    #[arg(
+        long,
+        value_name = "MODE",
+        help = "Sort by natural, mtime, size, exif, dimensions or random"
+    )]
+    sort: Option<String>,
+
+    #[arg(long, help = "Sort in descending order")]
+    descending: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "descending",
+        help = "Sort in ascending order, even if the config says descending"
+    )]
+    ascending: bool,
+
+    #[arg(long, value_name = "N", help = "Seed for the random sort order")]
+    seed: Option<u64>,
+
+    #[arg(long, help = "Validate the config file, report any problems and exit")]
+    check_config: bool,
+
+    #[arg(
+        long,
+        value_name = "PATH",
+        help = "Config file to use instead of ~/.config/eleviewr/config.toml (or set ELEVIEWR_CONFIG)"
+    )]
+    config: Option<PathBuf>,
+
+    #[arg(
+        long,
+        conflicts_with = "config",
+        help = "Ignore all config files and use the built-in defaults"
+    )]
+    no_config: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum CliCommand {
+    /// Send a command to the running viewer
+    Msg {
+        #[arg(
+            help = "next, prev, goto, open, zoom, quit, set-wallpaper or action, or one of the queries path, index and marks"
+        )]
+        command: String,
+        #[arg(
+            help = "The image number for goto, the path for open, in/out/reset or a factor for zoom, or the action name"
+        )]
+        args: Vec<String>,
+        #[arg(long, help = "Print the viewer's JSON answer as it is")]
+        json: bool,
+    },
 }
 
+/// Zoom limits and the factor each zoom step multiplies by
+const MIN_ZOOM: f32 = 0.1;
+const MAX_ZOOM: f32 = 20.0;
+const ZOOM_STEP: f32 = 1.25;
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct Uniforms {
     screen_aspect: f32,
     image_aspect: f32,
     scale_factor: f32,
-    _padding: f32,
+    exposure: f32,
+    gamma: f32,
+    tone_map: f32,
+    quad_left: f32,
+    quad_right: f32,
+}
+
+/// Exposure and gamma adjustment steps and limits
+const EXPOSURE_STEP: f32 = 0.5;
+const MAX_EXPOSURE: f32 = 10.0;
+const GAMMA_STEP: f32 = 0.1;
+const MIN_GAMMA: f32 = 0.2;
+const MAX_GAMMA: f32 = 5.0;
+
+/// What batch renaming starts with, and how many of its new names are shown
+/// before it is applied
+const RENAME_TEMPLATE: &str = "{date:%Y%m%d}_{n:04}{ext}";
+const RENAME_PREVIEW_ROWS: usize = 10;
+
+/// Pixel data ready for upload. 8-bit images go up as sRGB textures that
+/// the GPU linearises when sampling; anything deeper is converted to linear
+/// half floats so no precision is lost.
+struct TextureData {
+    format: wgpu::TextureFormat,
+    bytes: Vec<u8>,
+    bytes_per_pixel: u32,
+    /// Whether values can go beyond 1.0 and need tone mapping
+    hdr: bool,
+}
+
+impl TextureData {
+    fn new(img: &image::DynamicImage) -> Self {
+        use image::DynamicImage::*;
+
+        let to_half =
+            |value: f32| half::f16::from_f32(value.min(half::f16::MAX.to_f32())).to_le_bytes();
+        match img {
+            // Float images (EXR, Radiance HDR, float TIFFs) hold linear light
+            ImageRgb32F(_) | ImageRgba32F(_) => Self {
+                format: wgpu::TextureFormat::Rgba16Float,
+                bytes: img
+                    .to_rgba32f()
+                    .into_raw()
+                    .into_iter()
+                    .flat_map(to_half)
+                    .collect(),
+                bytes_per_pixel: 8,
+                hdr: true,
+            },
+            ImageLuma16(_) | ImageLumaA16(_) | ImageRgb16(_) | ImageRgba16(_) => {
+                let linear: Vec<[u8; 2]> = (0..=u16::MAX)
+                    .map(|value| to_half(srgb_to_linear(value as f32 / 65535.0)))
+                    .collect();
+                let bytes = img
+                    .to_rgba16()
+                    .pixels()
+                    .flat_map(|pixel| {
+                        let [r, g, b, a] = pixel.0;
+                        [
+                            linear[r as usize],
+                            linear[g as usize],
+                            linear[b as usize],
+                            to_half(a as f32 / 65535.0),
+                        ]
+                    })
+                    .flatten()
+                    .collect();
+                Self {
+                    format: wgpu::TextureFormat::Rgba16Float,
+                    bytes,
+                    bytes_per_pixel: 8,
+                    hdr: false,
+                }
+            }
+            _ => Self {
+                format: wgpu::TextureFormat::Rgba8UnormSrgb,
+                bytes: img.to_rgba8().into_raw(),
+                bytes_per_pixel: 4,
+                hdr: false,
+            },
+        }
+    }
+}
+
+/// An image on the GPU, drawn as one textured quad. Book mode draws two.
+struct Quad {
+    /// Kept alive for as long as the bind group samples it
+    _texture: wgpu::Texture,
+    bind_group: wgpu::BindGroup,
+    /// Each quad has uniforms of its own, as each is placed differently
+    uniform_buffer: wgpu::Buffer,
+    size: (u32, u32),
+    /// Whether the image holds HDR values that get tone mapped
+    hdr: bool,
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
 }
 
 #[derive(Clone)]
 enum AppState {
     Normal,
     DeleteConfirmation,
+    Prompt(Prompt),
+    /// Asking what to do about files already in a move or copy's destination
+    Collision {
+        transfer: Transfer,
+        existing: usize,
+    },
+}
+
+/// A line of text being typed in, such as a destination folder.
+#[derive(Clone)]
+struct Prompt {
+    action: PromptAction,
+    title: String,
+    text: String,
+}
+
+#[derive(Clone)]
+enum PromptAction {
+    MoveImages,
+    CopyImages,
+    /// Renaming one file, keeping its extension
+    Rename(PathBuf),
+    /// Renaming the marked images with a template
+    BatchRename(rename::BatchRename),
+    /// Saving an image that is only in memory to a file
+    SaveImage(MemoryImage),
+}
+
+/// A move or copy of the marked images, or the current one, into a folder.
+#[derive(Clone)]
+struct Transfer {
+    folder: PathBuf,
+    copy: bool,
+    /// Takes copied images out of the list as well, as sorting them into a
+    /// triage folder deals with them
+    dismiss: bool,
 }
 
 struct ImageViewer {
-    images: Vec<PathBuf>,
+    images: Vec<ImageSource>,
     current_index: usize,
-    image_texture: Option<wgpu::Texture>,
-    texture_bind_group: Option<wgpu::BindGroup>,
+    /// The directory or archive being browsed
+    root_dir: PathBuf,
+    /// Includes images in sub-folders of the root directory
+    recursive: bool,
+    sort_order: sort::SortOrder,
+    /// Whether a sort order was asked for on the command line; otherwise a
+    /// list of files keeps the order it was given in
+    sort_requested: bool,
+    /// Showing a list of paths given on the command line rather than a
+    /// directory or archive
+    file_list: bool,
+    /// Images marked for picking and batch operations
+    marked: HashSet<ImageSource>,
+    /// The folder images were last moved or copied to, offered again next
+    /// time
+    last_destination: Option<PathBuf>,
+    /// Images piped in or pasted that haven't been saved yet, which stay in
+    /// the list when the folder is scanned again
+    memory_images: Vec<MemoryImage>,
+    /// Watches the root directory for images being added or removed
+    watcher: Option<notify::RecommendedWatcher>,
+    watch_events: Option<mpsc::Receiver<notify::Result<notify::Event>>>,
+    /// Jumps to new images as they appear in the watched directory
+    follow_newest: bool,
+    /// How many levels of sub-folders to include; `None` for all of them
+    max_depth: Option<usize>,
+    /// The current image's file contents, kept for decoding other pages
+    /// and re-rendering vectors without reading it again
+    current_data: Option<Arc<[u8]>>,
+    /// The images on screen in reading order: the current image, then the
+    /// facing page of a spread in book mode
+    quads: Vec<Quad>,
     texture_bind_group_layout: wgpu::BindGroupLayout,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     render_pipeline: wgpu::RenderPipeline,
     sampler: wgpu::Sampler,
-    uniform_buffer: wgpu::Buffer,
-    uniform_bind_group: wgpu::BindGroup,
-    current_image_size: Option<(u32, u32)>,
     app_state: AppState,
     skip_delete_confirmation: bool,
     egui_ctx: egui::Context,
@@ -64,6 +357,48 @@ struct ImageViewer {
     egui_renderer: egui_wgpu::Renderer,
     notification_manager: NotificationManager,
     config_manager: ConfigManager,
+    /// Decoder error for the current image, shown in place of the image
+    load_error: Option<String>,
+    /// Decoder errors for every file known to be broken
+    broken_images: HashMap<ImageSource, String>,
+    /// Restricts navigation to broken files
+    broken_filter: bool,
+    broken_scan: Option<mpsc::Receiver<(ImageSource, Option<String>)>>,
+    /// Configured commands that are being waited for
+    running_commands: Vec<commands::Running>,
+    /// The control socket `eleviewr msg` talks to
+    ipc: Option<ipc::Server>,
+    /// Set by the exit action, for the event loop to act on
+    exit_requested: bool,
+    /// RAW files shown together with the JPEG of the same name, keyed by
+    /// the JPEG
+    raw_companions: HashMap<PathBuf, PathBuf>,
+    /// Magnification relative to fitting the window
+    zoom: f32,
+    /// Whether the current image is a vector that is rendered to fit
+    vector_image: bool,
+    /// Set when a vector image needs rendering again at a new size
+    vector_stale: bool,
+    /// The page shown from a multi-page file, counting from 0
+    current_page: usize,
+    page_count: usize,
+    /// Exposure adjustment in stops, kept while browsing
+    exposure: f32,
+    /// Display gamma adjustment, kept while browsing
+    gamma: f32,
+    color_manager: color::ColorManager,
+    /// Shows images in pairs side by side, as the spreads of a book
+    book_mode: bool,
+    /// Lays spreads out right to left, as manga are read
+    right_to_left: bool,
+    /// Shows the first image on its own, as a book's cover is
+    cover_offset: bool,
+    /// Image sizes looked up so far, for finding wide pages; `None` when the
+    /// size can't be read without decoding
+    page_sizes: HashMap<ImageSource, Option<(u32, u32)>>,
+    /// Where the spreads found so far start, in order from the first image;
+    /// cleared whenever the list, its order or the pairing changes
+    spread_starts: Vec<usize>,
 }
 
 impl ImageViewer {
@@ -74,10 +409,13 @@ impl ImageViewer {
 
     fn render_ui(
         &mut self,
-        size: winit::dpi::PhysicalSize<u32>,
+        window: &winit::window::Window,
     ) -> (Vec<egui::ClippedPrimitive>, egui::TexturesDelta) {
         self.notification_manager.update();
 
+        // Only keyboard input is taken from egui-winit, for typing into prompts
+        let size = window.inner_size();
+        let input = self.egui_state.take_egui_input(window);
         let raw_input = egui::RawInput {
             screen_rect: Some(egui::Rect::from_min_size(
                 egui::Pos2::ZERO,
@@ -89,18 +427,91 @@ impl ImageViewer {
                     .unwrap()
                     .as_secs_f64(),
             ),
+            modifiers: input.modifiers,
+            events: input
+                .events
+                .into_iter()
+                .filter(|event| {
+                    matches!(
+                        event,
+                        egui::Event::Text(_) | egui::Event::Key { .. } | egui::Event::Paste(_)
+                    )
+                })
+                .collect(),
             ..Default::default()
         };
 
         // Get a reference to notifications first to avoid borrow conflicts
         let notifications = self.notification_manager.get_notifications().clone();
         let app_state = self.app_state.clone();
+        let load_error = self
+            .load_error
+            .clone()
+            .map(|error| (self.window_title(), error));
+        let broken_filter = self.broken_filter.then(|| self.broken_count());
+        let page = (self.page_count > 1).then_some((self.current_page + 1, self.page_count));
+        let folder = self.current_folder();
+        let marks = (!self.marked.is_empty()).then(|| {
+            let current = self.images.get(self.current_index);
+            (
+                current.is_some_and(|source| self.marked.contains(source)),
+                self.marked.len(),
+            )
+        });
+        let collision = match &app_state {
+            AppState::Collision { transfer, existing } => {
+                Some((transfer.folder.display().to_string(), *existing))
+            }
+            _ => None,
+        };
+        let delete_count = match app_state {
+            AppState::DeleteConfirmation => self.targets().len(),
+            _ => 0,
+        };
+        if let AppState::Prompt(Prompt {
+            action: PromptAction::BatchRename(batch),
+            text,
+            ..
+        }) = &mut self.app_state
+        {
+            batch.update(text);
+        }
+        let mut prompt = match &self.app_state {
+            AppState::Prompt(prompt) => Some(prompt.clone()),
+            _ => None,
+        };
 
         let full_output = self.egui_ctx.run(raw_input, |ctx| {
+            if let Some((title, error)) = &load_error {
+                Self::render_load_error_static(ctx, title, error);
+            }
+            if let Some(broken) = broken_filter {
+                Self::render_broken_filter_static(ctx, broken);
+            }
+            if let Some((page, page_count)) = page {
+                Self::render_page_indicator_static(ctx, page, page_count);
+            }
+            if let Some(folder) = &folder {
+                Self::render_folder_indicator_static(ctx, folder);
+            }
+            if let Some((current_marked, count)) = marks {
+                Self::render_marks_indicator_static(ctx, current_marked, count);
+            }
             Self::render_notifications_static(ctx, &notifications);
-            Self::render_delete_confirmation_static(ctx, &app_state);
+            Self::render_delete_confirmation_static(ctx, &app_state, delete_count);
+            if let Some(prompt) = &mut prompt {
+                Self::render_prompt_static(ctx, prompt);
+            }
+            if let Some((folder, existing)) = &collision {
+                Self::render_collision_prompt_static(ctx, folder, *existing);
+            }
         });
 
+        // Keep what was typed into the prompt
+        if let (AppState::Prompt(current), Some(edited)) = (&mut self.app_state, prompt) {
+            current.text = edited.text;
+        }
+
         let clipped_primitives = self.egui_ctx.tessellate(full_output.shapes);
         (clipped_primitives, full_output.textures_delta)
     }
@@ -143,7 +554,266 @@ impl ImageViewer {
         }
     }
 
-    fn render_delete_confirmation_static(ctx: &egui::Context, app_state: &AppState) {
+    fn render_load_error_static(ctx: &egui::Context, title: &str, error: &str) {
+        egui::CentralPanel::default()
+            .frame(egui::Frame::none())
+            .show(ctx, |ui| {
+                ui.centered_and_justified(|ui| {
+                    ui.vertical_centered(|ui| {
+                        ui.label(
+                            egui::RichText::new("⚠ Cannot display this image")
+                                .size(18.0)
+                                .color(egui::Color32::WHITE),
+                        );
+                        ui.add_space(10.0);
+                        ui.label(
+                            egui::RichText::new(title.trim_start_matches("EleViewr - "))
+                                .size(14.0)
+                                .color(egui::Color32::LIGHT_GRAY),
+                        );
+                        ui.add_space(10.0);
+                        ui.label(
+                            egui::RichText::new(error)
+                                .size(12.0)
+                                .color(egui::Color32::from_rgb(220, 20, 60)),
+                        );
+                    });
+                });
+            });
+    }
+
+    fn render_broken_filter_static(ctx: &egui::Context, broken: usize) {
+        egui::Area::new("broken_filter")
+            .anchor(egui::Align2::LEFT_TOP, egui::Vec2::new(20.0, 20.0))
+            .show(ctx, |ui| {
+                egui::Frame::default()
+                    .fill(egui::Color32::from_black_alpha(180))
+                    .rounding(egui::Rounding::same(8.0))
+                    .inner_margin(egui::Margin::same(10.0))
+                    .show(ui, |ui| {
+                        ui.label(
+                            egui::RichText::new(format!("Showing broken files ({})", broken))
+                                .color(egui::Color32::YELLOW),
+                        );
+                    });
+            });
+    }
+
+    fn render_page_indicator_static(ctx: &egui::Context, page: usize, page_count: usize) {
+        egui::Area::new("page_indicator")
+            .anchor(egui::Align2::LEFT_BOTTOM, egui::Vec2::new(20.0, -20.0))
+            .show(ctx, |ui| {
+                egui::Frame::default()
+                    .fill(egui::Color32::from_black_alpha(180))
+                    .rounding(egui::Rounding::same(8.0))
+                    .inner_margin(egui::Margin::same(10.0))
+                    .show(ui, |ui| {
+                        ui.label(
+                            egui::RichText::new(format!("page {}/{}", page, page_count))
+                                .color(egui::Color32::WHITE),
+                        );
+                    });
+            });
+    }
+
+    fn render_folder_indicator_static(ctx: &egui::Context, folder: &str) {
+        egui::Area::new("folder_indicator")
+            .anchor(egui::Align2::RIGHT_BOTTOM, egui::Vec2::new(-20.0, -20.0))
+            .show(ctx, |ui| {
+                egui::Frame::default()
+                    .fill(egui::Color32::from_black_alpha(180))
+                    .rounding(egui::Rounding::same(8.0))
+                    .inner_margin(egui::Margin::same(10.0))
+                    .show(ui, |ui| {
+                        ui.label(
+                            egui::RichText::new(format!("{}/", folder)).color(egui::Color32::WHITE),
+                        );
+                    });
+            });
+    }
+
+    fn render_marks_indicator_static(ctx: &egui::Context, current_marked: bool, count: usize) {
+        egui::Area::new("marks_indicator")
+            .anchor(egui::Align2::CENTER_TOP, egui::Vec2::new(0.0, 20.0))
+            .show(ctx, |ui| {
+                egui::Frame::default()
+                    .fill(egui::Color32::from_black_alpha(180))
+                    .rounding(egui::Rounding::same(8.0))
+                    .inner_margin(egui::Margin::same(10.0))
+                    .show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            if current_marked {
+                                ui.label(
+                                    egui::RichText::new("✓ Marked")
+                                        .color(egui::Color32::GREEN)
+                                        .strong(),
+                                );
+                                ui.add_space(10.0);
+                            }
+                            ui.label(
+                                egui::RichText::new(format!("{} marked", count))
+                                    .color(egui::Color32::WHITE),
+                            );
+                        });
+                    });
+            });
+    }
+
+    fn render_prompt_static(ctx: &egui::Context, prompt: &mut Prompt) {
+        egui::Window::new("prompt")
+            .title_bar(false)
+            .resizable(false)
+            .movable(false)
+            .anchor(egui::Align2::CENTER_CENTER, egui::Vec2::ZERO)
+            .frame(
+                egui::Frame::window(&ctx.style())
+                    .fill(egui::Color32::from_gray(40))
+                    .inner_margin(egui::Margin::same(20.0)),
+            )
+            .show(ctx, |ui| {
+                ui.label(
+                    egui::RichText::new(&prompt.title)
+                        .size(16.0)
+                        .color(egui::Color32::WHITE),
+                );
+                ui.add_space(10.0);
+                let response =
+                    ui.add(egui::TextEdit::singleline(&mut prompt.text).desired_width(400.0));
+                response.request_focus();
+                if let PromptAction::BatchRename(batch) = &prompt.action {
+                    Self::render_rename_preview_static(ui, batch);
+                }
+                ui.add_space(10.0);
+                ui.label(
+                    egui::RichText::new("Enter to confirm, Escape to cancel")
+                        .size(12.0)
+                        .color(egui::Color32::LIGHT_GRAY),
+                );
+            });
+    }
+
+    /// Lists the old and new names of a batch rename, or why the template
+    /// can't be used.
+    fn render_rename_preview_static(ui: &mut egui::Ui, batch: &rename::BatchRename) {
+        ui.add_space(5.0);
+        ui.label(
+            egui::RichText::new("Fields: {name} {ext} {n} {n:04} {date} {date:%Y%m%d}")
+                .size(12.0)
+                .color(egui::Color32::LIGHT_GRAY),
+        );
+        ui.add_space(10.0);
+
+        let names = match &batch.names {
+            Ok(names) => names,
+            Err(error) => {
+                ui.label(
+                    egui::RichText::new(error)
+                        .size(14.0)
+                        .color(egui::Color32::from_rgb(220, 20, 60)),
+                );
+                return;
+            }
+        };
+        let file_name = |path: &Path| {
+            path.file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
+        };
+        egui::Grid::new("rename_preview")
+            .num_columns(3)
+            .spacing([10.0, 4.0])
+            .show(ui, |ui| {
+                for (original, name) in batch
+                    .originals
+                    .iter()
+                    .zip(names.iter())
+                    .take(RENAME_PREVIEW_ROWS)
+                {
+                    ui.label(
+                        egui::RichText::new(file_name(&original.path))
+                            .size(14.0)
+                            .color(egui::Color32::LIGHT_GRAY),
+                    );
+                    ui.label(
+                        egui::RichText::new("→")
+                            .size(14.0)
+                            .color(egui::Color32::GRAY),
+                    );
+                    ui.label(
+                        egui::RichText::new(file_name(name))
+                            .size(14.0)
+                            .color(egui::Color32::WHITE),
+                    );
+                    ui.end_row();
+                }
+            });
+        if names.len() > RENAME_PREVIEW_ROWS {
+            ui.label(
+                egui::RichText::new(format!("… and {} more", names.len() - RENAME_PREVIEW_ROWS))
+                    .size(14.0)
+                    .color(egui::Color32::LIGHT_GRAY),
+            );
+        }
+    }
+
+    fn render_collision_prompt_static(ctx: &egui::Context, folder: &str, existing: usize) {
+        egui::CentralPanel::default()
+            .frame(egui::Frame::none().fill(egui::Color32::from_black_alpha(180)))
+            .show(ctx, |ui| {
+                ui.centered_and_justified(|ui| {
+                    egui::Frame::window(&ctx.style())
+                        .fill(egui::Color32::from_gray(40))
+                        .stroke(egui::Stroke::new(2.0, egui::Color32::YELLOW))
+                        .inner_margin(egui::Margin::same(20.0))
+                        .show(ui, |ui| {
+                            ui.vertical_centered(|ui| {
+                                ui.add_space(10.0);
+                                ui.label(
+                                    egui::RichText::new("⚠ FILE ALREADY EXISTS")
+                                        .size(18.0)
+                                        .color(egui::Color32::WHITE),
+                                );
+                                ui.add_space(15.0);
+                                let message = if existing > 1 {
+                                    format!("{} of these files are already in {}", existing, folder)
+                                } else {
+                                    format!("A file of the same name is already in {}", folder)
+                                };
+                                ui.label(
+                                    egui::RichText::new(message)
+                                        .size(14.0)
+                                        .color(egui::Color32::LIGHT_GRAY),
+                                );
+                                ui.add_space(20.0);
+
+                                ui.horizontal(|ui| {
+                                    ui.label(
+                                        egui::RichText::new("S")
+                                            .color(egui::Color32::GREEN)
+                                            .strong(),
+                                    );
+                                    ui.label("Skip");
+                                    ui.add_space(20.0);
+                                    ui.label(
+                                        egui::RichText::new("R")
+                                            .color(egui::Color32::YELLOW)
+                                            .strong(),
+                                    );
+                                    ui.label("Keep both");
+                                    ui.add_space(20.0);
+                                    ui.label(
+                                        egui::RichText::new("O").color(egui::Color32::RED).strong(),
+                                    );
+                                    ui.label("Overwrite");
+                                });
+                            });
+                        });
+                });
+            });
+    }
+
+    fn render_delete_confirmation_static(ctx: &egui::Context, app_state: &AppState, count: usize) {
         if matches!(app_state, AppState::DeleteConfirmation) {
             egui::CentralPanel::default()
                 .frame(egui::Frame::none().fill(egui::Color32::from_black_alpha(180)))
@@ -163,9 +833,15 @@ impl ImageViewer {
                                     );
                                     ui.add_space(15.0);
                                     ui.label(
-                                        egui::RichText::new(
-                                            "Are you sure you want to delete this image?",
-                                        )
+                                        egui::RichText::new(if count > 1 {
+                                            format!(
+                                                "Are you sure you want to delete these {} images?",
+                                                count
+                                            )
+                                        } else {
+                                            "Are you sure you want to delete this image?"
+                                                .to_string()
+                                        })
                                         .size(14.0)
                                         .color(egui::Color32::LIGHT_GRAY),
                                     );
@@ -230,62 +906,485 @@ impl ImageViewer {
             .inner_margin(egui::Margin::same(10.0))
     }
     fn load_images_in_directory(&mut self, path: &Path) -> Result<()> {
-        self.images.clear();
-
         let search_dir = if path.is_file() {
-            // If it's a file, use its parent directory
-            path.parent().unwrap_or_else(|| Path::new("."))
+            // If it's a file, use its parent directory (`.` for a bare file
+            // name, whose parent is empty)
+            path.parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .unwrap_or_else(|| Path::new("."))
         } else {
             // If it's a directory, use it directly
             path
         };
 
-        let target_filename = if path.is_file() {
-            path.file_name()
+        let target = if path.is_file() {
+            Some(search_dir.join(path.file_name().unwrap_or_default()))
         } else {
             None
         };
 
-        let mut current_set = false;
+        self.root_dir = search_dir.to_path_buf();
+        self.scan_directory(target)
+    }
+
+    /// Lists the images in the root directory, and its sub-folders when
+    /// browsing recursively, selecting `target` if it is among them.
+    fn scan_directory(&mut self, mut target: Option<PathBuf>) -> Result<()> {
+        self.images.clear();
+        self.spread_starts.clear();
+        self.file_list = false;
+        let search_dir = self.root_dir.clone();
+        let mut found = self.list_directory(&search_dir);
+
+        self.raw_companions.clear();
+        if self.config_manager.get_config().raw.pair_with_jpeg {
+            self.raw_companions = pair_raw_with_jpeg(&found);
+            let paired: HashMap<&PathBuf, &PathBuf> = self
+                .raw_companions
+                .iter()
+                .map(|(jpeg, raw)| (raw, jpeg))
+                .collect();
+
+            // Opening the RAW half of a pair selects the pair
+            if let Some(jpeg) = target.as_ref().and_then(|target| paired.get(target)) {
+                target = Some((*jpeg).clone());
+            }
+            found.retain(|(path, _)| !paired.contains_key(path));
+        }
+
+        self.images = found
+            .into_iter()
+            .map(|(path, _)| ImageSource::File(path))
+            .chain(self.memory_images.iter().cloned().map(ImageSource::Memory))
+            .collect();
+        self.sort_order.sort(&mut self.images);
+
+        // Marks only cover the images listed
+        let listed: HashSet<&ImageSource> = self.images.iter().collect();
+        self.marked.retain(|source| listed.contains(source));
+
+        // Select the image that was opened, or the first one
+        self.current_index = target
+            .and_then(|target| {
+                self.images
+                    .iter()
+                    .position(|source| source.path() == Some(&target))
+            })
+            .unwrap_or(0);
 
-        for entry in WalkDir::new(search_dir)
-            .max_depth(1)
+        if self.images.is_empty() {
+            return Err(anyhow!(
+                "No image files found in directory: {}",
+                search_dir.display()
+            ));
+        }
+
+        self.watch_root();
+        Ok(())
+    }
+
+    /// Opens every image inside a ZIP/CBZ or TAR/CBT archive, in the chosen
+    /// sort order, as if it were a directory.
+    fn load_archive(&mut self, path: &Path) -> Result<()> {
+        let entries = self.archive_images(path)?;
+
+        self.raw_companions.clear();
+        self.file_list = false;
+        self.root_dir = path.to_path_buf();
+        self.watcher = None;
+        self.watch_events = None;
+        self.images = entries;
+        self.spread_starts.clear();
+        self.current_index = 0;
+
+        if self.images.is_empty() {
+            return Err(anyhow!(
+                "No image files found in archive: {}",
+                path.display()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Lists the images inside an archive in the chosen sort order.
+    fn archive_images(&self, path: &Path) -> Result<Vec<ImageSource>> {
+        let enabled_formats =
+            formats::enabled_formats(&self.config_manager.get_config().files.formats);
+
+        let mut entries: Vec<ImageSource> = source::list_archive(path)?
+            .into_iter()
+            .map(|(entry, header)| {
+                let source = ImageSource::ArchiveEntry {
+                    archive: path.to_path_buf(),
+                    entry,
+                };
+                (source, header)
+            })
+            .filter(|(source, header)| {
+                formats::detect_source_format(header, source)
+                    .is_some_and(|format| enabled_formats.contains(&format))
+            })
+            .map(|(source, _)| source)
+            .collect();
+        self.sort_order.sort(&mut entries);
+        Ok(entries)
+    }
+
+    /// Opens an explicit list of files, directories and archives. The order
+    /// they were given in is kept unless a sort was asked for; directories
+    /// and archives add their images in sort order.
+    fn load_paths(&mut self, paths: &[PathBuf]) -> Result<()> {
+        let enabled_formats =
+            formats::enabled_formats(&self.config_manager.get_config().files.formats);
+
+        let mut images = Vec::new();
+        let mut skipped = 0;
+        for path in paths {
+            if source::is_archive(path) {
+                match self.archive_images(path) {
+                    Ok(entries) => images.extend(entries),
+                    Err(_) => skipped += 1,
+                }
+            } else if path.is_dir() {
+                let mut found: Vec<ImageSource> = self
+                    .list_directory(path)
+                    .into_iter()
+                    .map(|(path, _)| ImageSource::File(path))
+                    .collect();
+                self.sort_order.sort(&mut found);
+                images.extend(found);
+            } else if formats::detect_file_format(path)
+                .is_some_and(|format| enabled_formats.contains(&format))
+            {
+                images.push(ImageSource::File(path.clone()));
+            } else {
+                skipped += 1;
+            }
+        }
+
+        // The same image given twice is only listed once
+        let mut seen = HashSet::new();
+        images.retain(|source| seen.insert(source.clone()));
+        if self.sort_requested {
+            self.sort_order.sort(&mut images);
+        }
+
+        self.raw_companions.clear();
+        self.file_list = true;
+        self.root_dir = PathBuf::new();
+        self.watcher = None;
+        self.watch_events = None;
+        self.images = images;
+        self.spread_starts.clear();
+        self.current_index = 0;
+
+        if skipped > 0 {
+            self.notification_manager
+                .add_warning(format!("Skipped {} path(s) that aren't images", skipped));
+        }
+        if self.images.is_empty() {
+            return Err(anyhow!("None of the given paths are images"));
+        }
+
+        Ok(())
+    }
+
+    /// Lists the images in a directory, and its sub-folders when browsing
+    /// recursively, unsorted.
+    fn list_directory(&self, dir: &Path) -> Vec<(PathBuf, Format)> {
+        let enabled_formats =
+            formats::enabled_formats(&self.config_manager.get_config().files.formats);
+
+        // Symlinks are followed, and walkdir reports a link back to one of
+        // its own ancestors as an error, so loops are skipped rather than
+        // walked forever
+        let mut seen = HashSet::new();
+        let mut found = Vec::new();
+        for entry in WalkDir::new(dir)
+            .max_depth(self.walk_depth())
+            .follow_links(true)
             .into_iter()
             .filter_map(|e| e.ok())
         {
             let entry_path = entry.path();
             if entry_path.is_file() {
-                let ext = entry_path
-                    .extension()
-                    .unwrap_or_default()
-                    .to_string_lossy()
-                    .to_lowercase();
-                if ["jpg", "jpeg", "png", "gif", "webp", "tiff", "bmp"].contains(&ext.as_str()) {
-                    if target_filename.is_some() && entry_path.file_name() == target_filename {
-                        self.current_index = self.images.len();
-                        current_set = true;
+                // The same file can be reached through more than one link
+                if let Ok(canonical) = entry_path.canonicalize() {
+                    if !seen.insert(canonical) {
+                        continue;
                     }
-                    self.images.push(entry_path.to_path_buf());
+                }
+                if let Some(format) = formats::detect_file_format(entry_path)
+                    .filter(|format| enabled_formats.contains(format))
+                {
+                    found.push((entry_path.to_path_buf(), format));
                 }
             }
         }
+        found
+    }
+
+    /// How deep below the root directory images are listed from; depth 1
+    /// is the directory's own files.
+    fn walk_depth(&self) -> usize {
+        match (self.recursive, self.max_depth) {
+            (false, _) => 1,
+            (true, Some(depth)) => depth.saturating_add(1),
+            (true, None) => usize::MAX,
+        }
+    }
+
+    /// Whether a file is deep enough inside the root directory to be listed.
+    fn in_scope(&self, path: &Path) -> bool {
+        path.strip_prefix(&self.root_dir)
+            .is_ok_and(|relative| (1..=self.walk_depth()).contains(&relative.components().count()))
+    }
 
-        // Sort images alphabetically
-        self.images.sort();
+    /// Starts watching the root directory for images being added or
+    /// removed, replacing any earlier watch.
+    fn watch_root(&mut self) {
+        self.watcher = None;
+        self.watch_events = None;
 
-        // If we didn't find the current image, reset to the first one
-        if !current_set && !self.images.is_empty() {
-            self.current_index = 0;
+        let (sender, receiver) = mpsc::channel();
+        let mode = if self.recursive {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        let watcher = notify::recommended_watcher(sender).and_then(|mut watcher| {
+            watcher.watch(&self.root_dir, mode)?;
+            Ok(watcher)
+        });
+
+        match watcher {
+            Ok(watcher) => {
+                self.watcher = Some(watcher);
+                self.watch_events = Some(receiver);
+            }
+            Err(e) => self.notification_manager.add_warning(format!(
+                "Not watching {} for changes: {}",
+                self.root_dir.display(),
+                e
+            )),
+        }
+    }
+
+    /// Applies changes to the watched directory: new images are inserted in
+    /// sort order and removed ones dropped, keeping the current image
+    /// selected. An image that was rewritten while shown is reloaded.
+    fn update_watch(&mut self) {
+        let Some(receiver) = &self.watch_events else {
+            return;
+        };
+
+        let current = self.images.get(self.current_index).cloned();
+        let current_path = current.as_ref().and_then(|source| source.path());
+        let mut changed = Vec::new();
+        let mut renamed_to = None;
+        let mut rewritten = false;
+        while let Ok(event) = receiver.try_recv() {
+            let Ok(event) = event else {
+                continue;
+            };
+            match (&event.kind, event.paths.as_slice()) {
+                (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), [from, to])
+                    if Some(from.as_path()) == current_path =>
+                {
+                    renamed_to = Some(to.clone());
+                }
+                (EventKind::Access(AccessKind::Close(AccessMode::Write)), [path])
+                    if Some(path.as_path()) == current_path =>
+                {
+                    rewritten = true;
+                }
+                _ => {}
+            }
+            changed.extend(event.paths);
+        }
+        if changed.is_empty() {
+            return;
+        }
+
+        // A folder moved in or out stands for every file inside it
+        let mut paths = Vec::new();
+        for path in changed {
+            if path.is_dir() {
+                paths.extend(
+                    WalkDir::new(&path)
+                        .follow_links(true)
+                        .into_iter()
+                        .filter_map(|e| e.ok())
+                        .map(|entry| entry.into_path()),
+                );
+            } else if !path.exists() {
+                paths.extend(
+                    self.images
+                        .iter()
+                        .filter_map(|source| source.path())
+                        .filter(|listed| listed.starts_with(&path))
+                        .map(Path::to_path_buf),
+                );
+            }
+            paths.push(path);
+        }
+        paths.sort();
+        paths.dedup();
+
+        let enabled_formats =
+            formats::enabled_formats(&self.config_manager.get_config().files.formats);
+        let pair_with_jpeg = self.config_manager.get_config().raw.pair_with_jpeg;
+        let mut added = Vec::new();
+        let mut removed = 0;
+        for path in paths {
+            let listed = self
+                .images
+                .iter()
+                .position(|source| source.path() == Some(&path));
+            let format = if path.is_file() && self.in_scope(&path) {
+                formats::detect_file_format(&path).filter(|format| enabled_formats.contains(format))
+            } else {
+                None
+            };
+
+            match (listed, format) {
+                (None, Some(Format::Raw)) if pair_with_jpeg && self.pair_new_raw(&path) => {}
+                (None, Some(_)) => {
+                    self.sort_order
+                        .insert(&mut self.images, ImageSource::File(path.clone()));
+                    added.push(path);
+                }
+                (Some(index), None) => {
+                    let source = self.images.remove(index);
+                    self.marked.remove(&source);
+                    self.raw_companions.remove(&path);
+                    removed += 1;
+                }
+                (None, None) => self.raw_companions.retain(|_, raw| *raw != path),
+                (Some(_), Some(_)) => {}
+            }
         }
+        if added.is_empty() && removed == 0 && !rewritten {
+            return;
+        }
+        self.spread_starts.clear();
 
         if self.images.is_empty() {
-            return Err(anyhow!(
-                "No image files found in directory: {}",
-                search_dir.display()
-            ));
+            self.current_index = 0;
+            self.current_data = None;
+            self.load_error = None;
+            self.quads.clear();
+            self.notification_manager
+                .add_warning("No images left in this folder".to_string());
+            return;
         }
 
-        Ok(())
+        let position = |path: &Path| {
+            self.images
+                .iter()
+                .position(|source| source.path() == Some(path))
+        };
+        // Events can arrive in any order, so go by when the files were written
+        let newest = added
+            .iter()
+            .max_by_key(|path| path.metadata().and_then(|data| data.modified()).ok())
+            .filter(|_| self.follow_newest);
+        let reload = if let Some(index) = newest.and_then(|newest| position(newest)) {
+            self.current_index = index;
+            true
+        } else if let Some(index) = renamed_to.as_deref().and_then(position) {
+            self.current_index = index;
+            true
+        } else if let Some(index) = current_path.and_then(position) {
+            self.current_index = index;
+            rewritten
+        } else {
+            // The current image went away; show the one that took its place
+            self.current_index = self.current_index.min(self.images.len() - 1);
+            true
+        };
+        if reload {
+            if self.showing_spreads() {
+                self.current_index = self.spread_start(self.current_index);
+            }
+            let _ = self.load_image();
+        }
+
+        match (newest, added.len(), removed) {
+            (Some(newest), _, _) => self.notification_manager.add_info(format!(
+                "New image: {}",
+                newest.file_name().unwrap_or_default().to_string_lossy()
+            )),
+            (None, 0, 0) => {}
+            (None, added, 0) => self
+                .notification_manager
+                .add_info(format!("{} new image(s)", added)),
+            (None, 0, removed) => self
+                .notification_manager
+                .add_info(format!("{} image(s) removed", removed)),
+            (None, added, removed) => self
+                .notification_manager
+                .add_info(format!("{} new image(s), {} removed", added, removed)),
+        }
+    }
+
+    /// Pairs a RAW file that just appeared with a listed JPEG of the same
+    /// name, if there is one.
+    fn pair_new_raw(&mut self, raw: &Path) -> bool {
+        let stem = raw.with_extension("");
+        let jpeg = self
+            .images
+            .iter()
+            .filter_map(|source| source.path())
+            .find(|path| {
+                path.with_extension("") == stem
+                    && formats::detect_file_format(path)
+                        == Some(Format::Image(image::ImageFormat::Jpeg))
+            });
+        match jpeg.map(Path::to_path_buf) {
+            Some(jpeg) => {
+                self.raw_companions.insert(jpeg, raw.to_path_buf());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Turns following new images on or off. Turning it on jumps to the
+    /// most recently modified image straight away.
+    fn toggle_follow_newest(&mut self) {
+        self.follow_newest = !self.follow_newest;
+        if !self.follow_newest {
+            self.notification_manager
+                .add_info("No longer following new images".to_string());
+            return;
+        }
+
+        let newest = self
+            .images
+            .iter()
+            .enumerate()
+            .filter_map(|(index, source)| {
+                let modified = source.path()?.metadata().ok()?.modified().ok()?;
+                Some((modified, index))
+            })
+            .max();
+        if let Some((_, index)) = newest {
+            self.current_index = index;
+            if self.showing_spreads() {
+                self.current_index = self.spread_start(index);
+            }
+            let _ = self.load_image();
+        }
+        if self.watcher.is_some() {
+            self.notification_manager
+                .add_info("Following new images".to_string());
+        } else {
+            self.notification_manager.add_warning(
+                "Following new images, but this folder isn't being watched".to_string(),
+            );
+        }
     }
 
     fn load_image(&mut self) -> Result<(String, (u32, u32))> {
@@ -293,132 +1392,1918 @@ impl ImageViewer {
             return Err(anyhow!("No images loaded"));
         }
 
-        let img_path = &self.images[self.current_index];
+        let source = self.images[self.current_index].clone();
         self.notification_manager
-            .add_info(format!("Loading image: {}", img_path.display()));
+            .add_info(format!("Loading image: {}", source.display()));
 
-        let img = image::open(img_path)?;
-        let dimensions = img.dimensions();
-        let rgba = img.to_rgba8();
+        self.zoom = 1.0;
+        self.vector_stale = false;
+        self.current_page = 0;
 
-        let texture_size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth_or_array_layers: 1,
+        let data: Arc<[u8]> = match source.read() {
+            Ok(data) => data.into(),
+            Err(e) => {
+                self.current_data = None;
+                self.vector_image = false;
+                self.page_count = 1;
+                self.show_load_error(&source, &e);
+                return Err(e);
+            }
         };
-        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
-            label: Some("Image Texture"),
-            size: texture_size,
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: wgpu::TextureDimension::D2,
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
+        self.vector_image =
+            formats::detect_source_format(&data, &source).is_some_and(Format::is_vector);
+        self.page_count = formats::page_count(&data, &source);
+        self.current_data = Some(data);
+
+        self.quads.clear();
+        self.show_spread()
+    }
+
+    /// Shows the current page of the current image, and in book mode the
+    /// facing page next to it.
+    fn show_spread(&mut self) -> Result<(String, (u32, u32))> {
+        let (_, dimensions) = self.show_page()?;
+        self.quads.truncate(1);
+        if self.showing_spreads() && self.spread_len(self.current_index) == 2 {
+            self.show_facing_page(self.current_index + 1);
+        }
+
+        Ok((self.window_title(), dimensions))
+    }
+
+    /// Adds the facing page of a spread. A page that can't be shown is left
+    /// out, rather than covering the whole spread with a placeholder.
+    fn show_facing_page(&mut self, index: usize) {
+        let source = self.images[index].clone();
+        let options = formats::DecodeOptions {
+            page: 0,
+            ..self.decode_options()
+        };
+        let decoded = source.read().and_then(|data| {
+            let img = formats::decode_image(&data, &source, options)?;
+            Ok((img, data))
+        });
+
+        match decoded {
+            Ok((mut img, data)) => {
+                self.broken_images.remove(&source);
+                if let Err(e) = self.color_manager.convert(&mut img, &data, &source, 0) {
+                    self.notification_manager
+                        .add_warning(format!("Showing without color management: {}", e));
+                }
+                self.set_page_size(source, img.dimensions());
+                let quad = self.create_quad(&img);
+                self.quads.push(quad);
+                self.update_uniforms();
+            }
+            Err(e) => {
+                self.notification_manager.add_warning(format!(
+                    "Failed to show {}: {}",
+                    source.name(),
+                    e
+                ));
+                self.broken_images.insert(source, e.to_string());
+            }
+        }
+    }
+
+    /// Whether images are paired into spreads. Filtering to broken files
+    /// shows them one at a time, as the pairs would no longer line up.
+    fn showing_spreads(&self) -> bool {
+        self.book_mode && !self.broken_filter
+    }
+
+    /// Whether the image at `index` is wider than it is tall, such as a
+    /// scanned double page, and so makes up a spread by itself.
+    fn is_wide(&mut self, index: usize) -> bool {
+        let source = &self.images[index];
+        let size = match self.page_sizes.get(source) {
+            Some(size) => *size,
+            None => {
+                let size = formats::image_dimensions(source);
+                self.page_sizes.insert(source.clone(), size);
+                size
+            }
+        };
+        size.is_some_and(|(width, height)| width > height)
+    }
+
+    /// The number of images in the spread starting at `start`. Wide images,
+    /// and the cover when the cover offset is on, stand alone.
+    fn spread_len(&mut self, start: usize) -> usize {
+        let single = (start == 0 && self.cover_offset)
+            || start + 1 >= self.images.len()
+            || self.is_wide(start)
+            || self.is_wide(start + 1);
+        if single {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// The first image of the spread containing `index`. Spreads are paired
+    /// up from the start of the list, so they don't shift depending on where
+    /// reading started.
+    fn spread_start(&mut self, index: usize) -> usize {
+        if self.spread_starts.is_empty() {
+            self.spread_starts.push(0);
+        }
+        // Only pair up as far as `index`, remembering the spreads on the way
+        while let Some(&last) = self.spread_starts.last().filter(|&&last| last <= index) {
+            let next = last + self.spread_len(last);
+            if index < next {
+                return last;
+            }
+            self.spread_starts.push(next);
+        }
+        let position = self.spread_starts.partition_point(|&start| start <= index);
+        self.spread_starts[position - 1]
+    }
+
+    /// Records the size of a decoded image, pairing the spreads up again if
+    /// it turns out wider or narrower than its header said.
+    fn set_page_size(&mut self, source: ImageSource, size: (u32, u32)) {
+        let wide = |size: Option<&Option<(u32, u32)>>| {
+            size.copied()
+                .flatten()
+                .is_some_and(|(width, height)| width > height)
+        };
+        if wide(self.page_sizes.get(&source)) != wide(Some(&Some(size))) {
+            self.spread_starts.clear();
+        }
+        self.page_sizes.insert(source, Some(size));
+    }
+
+    /// Moves to the next or previous spread, wrapping around at the ends.
+    fn step_spread(&mut self, step: isize) -> Result<(String, (u32, u32))> {
+        let start = self.spread_start(self.current_index);
+        self.current_index = if step > 0 {
+            let next = start + self.spread_len(start);
+            if next < self.images.len() {
+                next
+            } else {
+                0
+            }
+        } else if start > 0 {
+            self.spread_start(start - 1)
+        } else {
+            self.spread_start(self.images.len() - 1)
+        };
+        self.load_image()
+    }
+
+    /// Turns book mode on or off, lining the current image up with the
+    /// start of its spread.
+    fn toggle_book_mode(&mut self) {
+        self.book_mode = !self.book_mode;
+        self.notification_manager.add_info(
+            if self.book_mode {
+                "Book mode on"
+            } else {
+                "Book mode off"
+            }
+            .to_string(),
+        );
+        self.reload_spread();
+    }
+
+    fn toggle_reading_direction(&mut self) {
+        self.right_to_left = !self.right_to_left;
+        self.update_uniforms();
+        self.notification_manager.add_info(
+            if self.right_to_left {
+                "Reading right to left"
+            } else {
+                "Reading left to right"
+            }
+            .to_string(),
+        );
+    }
+
+    /// Shifts how images pair up by one, for books whose cover is missing
+    /// or scanned as part of a spread.
+    fn toggle_cover_offset(&mut self) {
+        self.cover_offset = !self.cover_offset;
+        self.spread_starts.clear();
+        self.notification_manager.add_info(
+            if self.cover_offset {
+                "Cover shown on its own"
+            } else {
+                "Cover paired with the next page"
+            }
+            .to_string(),
+        );
+        self.reload_spread();
+    }
+
+    /// Shows the spread containing the current image again after the
+    /// pairing has changed.
+    fn reload_spread(&mut self) {
+        if self.images.is_empty() {
+            return;
+        }
+        if self.showing_spreads() {
+            self.current_index = self.spread_start(self.current_index);
+        }
+        let _ = self.load_image();
+    }
+
+    /// Decodes and displays the current page of the current image.
+    fn show_page(&mut self) -> Result<(String, (u32, u32))> {
+        let source = self.images[self.current_index].clone();
+        let Some(data) = self.current_data.clone() else {
+            return Err(anyhow!("No image loaded"));
+        };
+        let mut img = match formats::decode_image(&data, &source, self.decode_options()) {
+            Ok(img) => img,
+            Err(e) => {
+                self.show_load_error(&source, &e);
+                return Err(e);
+            }
+        };
+        self.broken_images.remove(&source);
+        self.set_page_size(source.clone(), img.dimensions());
+        self.load_error = None;
+        if let Err(e) = self
+            .color_manager
+            .convert(&mut img, &data, &source, self.current_page)
+        {
+            self.notification_manager
+                .add_warning(format!("Showing without color management: {}", e));
+        }
+        let dimensions = img.dimensions();
+        self.upload_texture(&img);
+
+        // Return the image name and dimensions
+        Ok((self.window_title(), dimensions))
+    }
+
+    /// Leaves a placeholder up in place of an image that can't be shown, so
+    /// navigation can carry on past it.
+    fn show_load_error(&mut self, source: &ImageSource, error: &anyhow::Error) {
+        self.broken_images.insert(source.clone(), error.to_string());
+        self.load_error = Some(error.to_string());
+        self.quads.clear();
+    }
+
+    /// Moves `step` pages through a multi-page file, stopping at either end
+    /// rather than moving on to another file.
+    fn step_page(&mut self, step: isize) -> Result<(String, (u32, u32))> {
+        if self.images.is_empty() {
+            return Err(anyhow!("No images loaded"));
+        }
+
+        let page = self.current_page as isize + step;
+        if page < 0 || page >= self.page_count as isize {
+            return Err(anyhow!(if self.page_count == 1 {
+                "This image has only one page"
+            } else if page < 0 {
+                "Already on the first page"
+            } else {
+                "Already on the last page"
+            }));
+        }
+
+        self.current_page = page as usize;
+        self.show_page()
+    }
+
+    /// Shows a decoded image as the current one, keeping any facing page.
+    fn upload_texture(&mut self, img: &image::DynamicImage) {
+        let quad = self.create_quad(img);
+        match self.quads.first_mut() {
+            Some(current) => *current = quad,
+            None => self.quads.push(quad),
+        }
+        self.update_uniforms();
+    }
+
+    /// Uploads an image to the GPU, ready to be drawn.
+    fn create_quad(&self, img: &image::DynamicImage) -> Quad {
+        let dimensions = img.dimensions();
+        let data = TextureData::new(img);
+
+        let texture_size = wgpu::Extent3d {
+            width: dimensions.0,
+            height: dimensions.1,
+            depth_or_array_layers: 1,
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Image Texture"),
+            size: texture_size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: data.format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+
+        self.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &data.bytes,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(data.bytes_per_pixel * dimensions.0),
+                rows_per_image: Some(dimensions.1),
+            },
+            texture_size,
+        );
+
+        let uniform_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Quad Uniform Buffer"),
+            size: std::mem::size_of::<Uniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Create texture view and bind group
+        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Texture Bind Group"),
+            layout: &self.texture_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        Quad {
+            _texture: texture,
+            bind_group,
+            uniform_buffer,
+            size: dimensions,
+            hdr: data.hdr,
+        }
+    }
+
+    /// Points the shader at the current images and window sizes and zoom.
+    /// The images of a spread are scaled to the same height and laid out
+    /// side by side, as one picture.
+    fn update_uniforms(&self) {
+        if self.quads.is_empty() {
+            return;
+        }
+
+        let screen_width = self.config.width as f32;
+        let screen_height = self.config.height as f32;
+        let screen_aspect = screen_width / screen_height;
+
+        let aspect = |quad: &Quad| quad.size.0 as f32 / quad.size.1 as f32;
+        let spread_aspect: f32 = self.quads.iter().map(aspect).sum();
+
+        let mut order: Vec<&Quad> = self.quads.iter().collect();
+        if self.right_to_left {
+            order.reverse();
+        }
+
+        let mut left = -1.0;
+        for quad in order {
+            let width = 2.0 * aspect(quad) / spread_aspect;
+            let uniforms = Uniforms {
+                screen_aspect,
+                image_aspect: spread_aspect,
+                scale_factor: self.zoom,
+                exposure: self.exposure,
+                gamma: self.gamma,
+                tone_map: if quad.hdr { 1.0 } else { 0.0 },
+                quad_left: left,
+                quad_right: left + width,
+            };
+            self.queue
+                .write_buffer(&quad.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
+            left += width;
+        }
+    }
+
+    /// Changes the zoom level, re-rendering vector images so they stay sharp.
+    fn set_zoom(&mut self, zoom: f32) {
+        self.zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        self.update_uniforms();
+        if self.vector_image {
+            self.vector_stale = true;
+        }
+        self.notification_manager
+            .add_info(format!("Zoom: {:.0}%", self.zoom * 100.0));
+    }
+
+    /// Switches between color-managed and raw rendering, for comparison.
+    fn toggle_color_management(&mut self) {
+        if !color::ColorManager::is_available() {
+            self.notification_manager.add_warning(
+                "Color management is not enabled in this build (rebuild with `--features icc`)"
+                    .to_string(),
+            );
+            return;
+        }
+
+        self.color_manager.enabled = !self.color_manager.enabled;
+        if !self.images.is_empty() {
+            let _ = self.show_spread();
+        }
+        self.notification_manager.add_info(
+            if self.color_manager.enabled {
+                "Color management on"
+            } else {
+                "Color management off"
+            }
+            .to_string(),
+        );
+    }
+
+    /// Adjusts how bright the image is shown, for inspecting HDR content.
+    fn set_exposure(&mut self, exposure: f32, gamma: f32) {
+        self.exposure = exposure.clamp(-MAX_EXPOSURE, MAX_EXPOSURE);
+        self.gamma = gamma.clamp(MIN_GAMMA, MAX_GAMMA);
+        self.update_uniforms();
+        self.notification_manager.add_info(format!(
+            "Exposure: {:+.1} EV, gamma: {:.1}",
+            self.exposure, self.gamma
+        ));
+    }
+
+    /// Renders the current vector image again at the size it is displayed
+    /// at, once the window or zoom has stopped changing for this frame.
+    fn update_vector_render(&mut self) {
+        if !std::mem::take(&mut self.vector_stale) {
+            return;
+        }
+        let (Some(source), Some(data)) = (
+            self.images.get(self.current_index).cloned(),
+            self.current_data.clone(),
+        ) else {
+            return;
+        };
+
+        match formats::decode_image(&data, &source, self.decode_options()) {
+            Ok(img) => self.upload_texture(&img),
+            Err(e) => self.notification_manager.add_error(format!(
+                "Failed to render {}: {}",
+                source.display(),
+                e
+            )),
+        }
+    }
+
+    fn decode_options(&self) -> formats::DecodeOptions {
+        // Vectors are rendered at the size they take up on screen, within
+        // what the GPU accepts as a texture
+        let max_size = self.device.limits().max_texture_dimension_2d as f32;
+        let fit = |size: u32| (size as f32 * self.zoom).min(max_size) as u32;
+
+        formats::DecodeOptions {
+            raw_full_decode: self.config_manager.get_config().raw.full_decode,
+            fit: Some((fit(self.config.width), fit(self.config.height))),
+            page: self.current_page,
+        }
+    }
+
+    fn window_title(&self) -> String {
+        match self.images.get(self.current_index) {
+            Some(source) => {
+                let mut title = format!("EleViewr - {}", self.display_name(source));
+                if self.quads.len() > 1 {
+                    if let Some(facing) = self.images.get(self.current_index + 1) {
+                        title.push_str(&format!(" + {}", facing.name()));
+                    }
+                }
+                if let Some(raw) = source.path().and_then(|path| self.raw_companions.get(path)) {
+                    title.push_str(&format!(
+                        " (+ {})",
+                        raw.extension().unwrap_or_default().to_string_lossy()
+                    ));
+                }
+                if self.page_count > 1 {
+                    title.push_str(&format!(
+                        " (page {}/{})",
+                        self.current_page + 1,
+                        self.page_count
+                    ));
+                }
+                title
+            }
+            None => "EleViewr".to_string(),
+        }
+    }
+
+    /// The image's name, with the sub-folder it is in when browsing
+    /// recursively.
+    fn display_name(&self, source: &ImageSource) -> String {
+        match source
+            .path()
+            .and_then(|path| path.strip_prefix(&self.root_dir).ok())
+        {
+            Some(relative) if self.recursive => relative.to_string_lossy().into_owned(),
+            _ => source.name(),
+        }
+    }
+
+    /// The current image's folder relative to the root directory, or `None`
+    /// when it is the root itself.
+    fn current_folder(&self) -> Option<String> {
+        let folder = self.images.get(self.current_index)?.folder();
+        let relative = folder.strip_prefix(&self.root_dir).unwrap_or(&folder);
+        (!relative.as_os_str().is_empty()).then(|| relative.to_string_lossy().into_owned())
+    }
+
+    /// Turns including sub-folders on or off, keeping the current image
+    /// selected if it is still listed.
+    fn toggle_recursive(&mut self) {
+        if self.file_list {
+            self.notification_manager.add_info(
+                "Sub-folders can only be toggled when browsing a single folder".to_string(),
+            );
+            return;
+        }
+        if self.root_dir.is_file() {
+            self.notification_manager
+                .add_info("Archives always include their sub-folders".to_string());
+            return;
+        }
+
+        self.recursive = !self.recursive;
+        let current = self
+            .images
+            .get(self.current_index)
+            .and_then(|source| source.path())
+            .map(Path::to_path_buf);
+        match self.scan_directory(current) {
+            Ok(()) => {
+                let _ = self.load_image();
+                self.notification_manager.add_info(format!(
+                    "{} ({} images)",
+                    if self.recursive {
+                        "Including sub-folders"
+                    } else {
+                        "Showing this folder only"
+                    },
+                    self.images.len()
+                ));
+            }
+            Err(e) => {
+                self.quads.clear();
+                self.notification_manager.add_error(e.to_string());
+            }
+        }
+    }
+
+    /// Marks or unmarks the current image. Only files on disk can be marked,
+    /// as marks are for handing paths on to other programs.
+    fn toggle_mark(&mut self) {
+        let Some(source) = self.images.get(self.current_index).cloned() else {
+            return;
+        };
+        if source.path().is_none() {
+            self.notification_manager
+                .add_warning("Only files on disk can be marked".to_string());
+            return;
+        }
+
+        let message = if self.marked.remove(&source) {
+            "Unmarked"
+        } else {
+            self.marked.insert(source.clone());
+            "Marked"
+        };
+        self.notification_manager.add_info(format!(
+            "{} {} ({} marked)",
+            message,
+            source.name(),
+            self.marked.len()
+        ));
+    }
+
+    /// The paths of the marked images, in list order.
+    fn marked_paths(&self) -> Vec<&Path> {
+        self.images
+            .iter()
+            .filter(|source| self.marked.contains(*source))
+            .filter_map(|source| source.path())
+            .collect()
+    }
+
+    /// The images a batch operation applies to: the marked ones in list
+    /// order, or the current image when nothing is marked.
+    fn targets(&self) -> Vec<ImageSource> {
+        if self.marked.is_empty() {
+            self.images
+                .get(self.current_index)
+                .cloned()
+                .into_iter()
+                .collect()
+        } else {
+            self.images
+                .iter()
+                .filter(|source| self.marked.contains(*source))
+                .cloned()
+                .collect()
+        }
+    }
+
+    /// The files a batch operation applies to, or `None` when the current
+    /// image isn't a file of its own (it is inside an archive, or unsaved).
+    fn target_paths(&self) -> Option<Vec<PathBuf>> {
+        self.targets()
+            .iter()
+            .map(|source| source.path().map(Path::to_path_buf))
+            .collect()
+    }
+
+    /// The images marking everything applies to: the files on disk, and only
+    /// the broken ones while those are filtered for.
+    fn markable(&self) -> Vec<ImageSource> {
+        (0..self.images.len())
+            .filter(|&index| self.is_visible(index))
+            .map(|index| &self.images[index])
+            .filter(|source| source.path().is_some())
+            .cloned()
+            .collect()
+    }
+
+    /// Marks every image, or unmarks them all if they already are.
+    fn toggle_mark_all(&mut self) {
+        let markable = self.markable();
+        if markable.is_empty() {
+            self.notification_manager
+                .add_warning("Only files on disk can be marked".to_string());
+            return;
+        }
+
+        if markable.iter().all(|source| self.marked.contains(source)) {
+            for source in &markable {
+                self.marked.remove(source);
+            }
+            self.notification_manager
+                .add_info("Unmarked all images".to_string());
+        } else {
+            self.marked.extend(markable);
+            self.notification_manager
+                .add_info(format!("Marked all images ({} marked)", self.marked.len()));
+        }
+    }
+
+    fn invert_marks(&mut self) {
+        for source in self.markable() {
+            if !self.marked.remove(&source) {
+                self.marked.insert(source);
+            }
+        }
+        self.notification_manager
+            .add_info(format!("Inverted marks ({} marked)", self.marked.len()));
+    }
+
+    /// Asks for the folder to move or copy the marked images, or the current
+    /// one, to.
+    fn open_prompt(&mut self, action: PromptAction) {
+        let targets = self.targets();
+        if targets.is_empty() {
+            return;
+        }
+        if targets.iter().any(|source| source.path().is_none()) {
+            self.notification_manager
+                .add_error("Only files on disk can be moved or copied".to_string());
+            return;
+        }
+
+        let verb = if matches!(action, PromptAction::CopyImages) {
+            "Copy"
+        } else {
+            "Move"
+        };
+        let title = match targets.as_slice() {
+            [source] => format!("{} {} to folder:", verb, source.name()),
+            _ => format!("{} {} marked images to folder:", verb, targets.len()),
+        };
+        let folder = self
+            .last_destination
+            .clone()
+            .unwrap_or_else(|| targets[0].folder());
+        let mut text = folder.display().to_string();
+        if !text.is_empty() && !text.ends_with('/') {
+            text.push('/');
+        }
+
+        self.app_state = AppState::Prompt(Prompt {
+            action,
+            title,
+            text,
+        });
+    }
+
+    fn submit_prompt(&mut self) {
+        let AppState::Prompt(prompt) = std::mem::replace(&mut self.app_state, AppState::Normal)
+        else {
+            return;
+        };
+        let text = prompt.text.trim();
+        if text.is_empty() {
+            return;
+        }
+
+        let renames = match &prompt.action {
+            PromptAction::MoveImages | PromptAction::CopyImages => {
+                let copy = matches!(prompt.action, PromptAction::CopyImages);
+                let folder = config::expand_path(text);
+                self.last_destination = Some(folder.clone());
+                self.transfer_images(Transfer {
+                    folder,
+                    copy,
+                    dismiss: false,
+                });
+                return;
+            }
+            PromptAction::SaveImage(image) => {
+                let path = config::expand_path(text);
+                match self.save_image(image, &path) {
+                    Ok(()) => self
+                        .notification_manager
+                        .add_success(format!("Saved {}", path.display())),
+                    Err(e) => {
+                        self.notification_manager.add_error(format!("{:#}", e));
+                        self.app_state = AppState::Prompt(prompt);
+                    }
+                }
+                return;
+            }
+            PromptAction::Rename(path) => {
+                let mut name = std::ffi::OsString::from(text);
+                if let Some(extension) = path.extension() {
+                    name.push(".");
+                    name.push(extension);
+                }
+                vec![(path.clone(), path.with_file_name(name))]
+            }
+            PromptAction::BatchRename(batch) => match &batch.names {
+                Ok(names) => batch
+                    .originals
+                    .iter()
+                    .map(|original| original.path.clone())
+                    .zip(names.iter().cloned())
+                    .collect(),
+                // The preview already shows what is wrong with the template
+                Err(_) => {
+                    self.app_state = AppState::Prompt(prompt);
+                    return;
+                }
+            },
+        };
+
+        if renames.iter().all(|(from, to)| from == to) {
+            return;
+        }
+        match self.rename_images(&renames) {
+            Ok(()) => {
+                let message = match renames.as_slice() {
+                    [(from, to)] => format!(
+                        "Renamed {} to {}",
+                        from.file_name().unwrap_or_default().to_string_lossy(),
+                        to.file_name().unwrap_or_default().to_string_lossy()
+                    ),
+                    _ => format!("Renamed {} images", renames.len()),
+                };
+                self.notification_manager.add_info(message);
+            }
+            Err(e) => {
+                self.notification_manager.add_error(format!("{:#}", e));
+                // Leave the prompt open so the name can be fixed
+                self.app_state = AppState::Prompt(prompt);
+            }
+        }
+    }
+
+    /// Adds an image that only exists in memory after the current one, and
+    /// selects it.
+    fn add_memory_image(&mut self, image: MemoryImage) {
+        let index = if self.images.is_empty() {
+            0
+        } else {
+            self.current_index + 1
+        };
+        self.images
+            .insert(index, ImageSource::Memory(image.clone()));
+        self.memory_images.push(image);
+        self.current_index = index;
+    }
+
+    /// Shows the image on the clipboard, as an unsaved image in the list.
+    fn paste_from_clipboard(&mut self) {
+        let data = match clipboard::paste_image() {
+            Ok(data) => data,
+            Err(e) => {
+                self.notification_manager.add_error(format!("{:#}", e));
+                return;
+            }
+        };
+        let Some(format) = formats::detect_format(&data, None) else {
+            self.notification_manager
+                .add_error("The image on the clipboard is in an unknown format".to_string());
+            return;
+        };
+
+        self.add_memory_image(MemoryImage::new(unsaved_name("clipboard", format), data));
+        if self.load_image().is_ok() {
+            self.notification_manager
+                .add_success("Pasted image from the clipboard (F3 saves it)".to_string());
+        }
+    }
+
+    /// Asks where to save the current image, if it is only in memory.
+    fn open_save_prompt(&mut self) {
+        let Some(ImageSource::Memory(image)) = self.images.get(self.current_index).cloned() else {
+            self.notification_manager
+                .add_info("Only pasted and piped images need saving".to_string());
+            return;
+        };
+
+        let folder = self
+            .last_destination
+            .clone()
+            .or_else(|| self.root_dir.is_dir().then(|| self.root_dir.clone()))
+            .or_else(|| std::env::current_dir().ok())
+            .unwrap_or_default();
+        self.app_state = AppState::Prompt(Prompt {
+            title: format!("Save {} as:", image.name),
+            text: folder.join(&image.name).display().to_string(),
+            action: PromptAction::SaveImage(image),
+        });
+    }
+
+    /// Writes an image that was only in memory to a new file, which takes
+    /// its place in the list. The data is written as it is, so the file's
+    /// format is the one it was piped or pasted in.
+    fn save_image(&mut self, image: &MemoryImage, path: &Path) -> Result<()> {
+        use std::io::Write;
+
+        if let Some(folder) = path
+            .parent()
+            .filter(|folder| !folder.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(folder)
+                .map_err(|e| anyhow!("Cannot create {}: {}", folder.display(), e))?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::AlreadyExists => {
+                    anyhow!("{} already exists", path.display())
+                }
+                _ => anyhow!("Cannot write {}: {}", path.display(), e),
+            })?;
+        if let Err(e) = file.write_all(&image.data) {
+            let _ = std::fs::remove_file(path);
+            return Err(anyhow!("Cannot write {}: {}", path.display(), e));
+        }
+
+        self.memory_images.retain(|other| other != image);
+        let unsaved = ImageSource::Memory(image.clone());
+        let saved = ImageSource::File(path.to_path_buf());
+        for source in &mut self.images {
+            if *source == unsaved {
+                *source = saved.clone();
+            }
+        }
+        if let Some(error) = self.broken_images.remove(&unsaved) {
+            self.broken_images.insert(saved.clone(), error);
+        }
+        if let Some(size) = self.page_sizes.remove(&unsaved) {
+            self.page_sizes.insert(saved, size);
+        }
+        self.last_destination = path.parent().map(Path::to_path_buf);
+        Ok(())
+    }
+
+    /// Asks for a new name for the current image, or for a template to
+    /// rename the marked images with.
+    fn open_rename(&mut self) {
+        let Some(paths) = self.target_paths() else {
+            self.notification_manager
+                .add_error("Only files on disk can be renamed".to_string());
+            return;
+        };
+
+        let prompt = match paths.as_slice() {
+            [] => return,
+            [path] => Prompt {
+                title: format!(
+                    "Rename {} (the extension is kept):",
+                    path.file_name().unwrap_or_default().to_string_lossy()
+                ),
+                text: path
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                action: PromptAction::Rename(path.clone()),
+            },
+            _ => Prompt {
+                title: format!("Rename {} marked images:", paths.len()),
+                text: RENAME_TEMPLATE.to_string(),
+                action: PromptAction::BatchRename(rename::BatchRename::new(paths)),
+            },
+        };
+        self.app_state = AppState::Prompt(prompt);
+    }
+
+    /// Renames files in the list, taking their RAW companions along, and
+    /// updates everything that refers to them by path.
+    fn rename_images(&mut self, renames: &[(PathBuf, PathBuf)]) -> Result<()> {
+        let mut all = renames.to_vec();
+        for (from, to) in renames {
+            if let Some(raw) = self.raw_companions.get(from) {
+                let raw_to = to.with_extension(raw.extension().unwrap_or_default());
+                all.push((raw.clone(), raw_to));
+            }
+        }
+        rename::check(&all)?;
+        rename::apply(&all)?;
+
+        let moved: HashMap<&PathBuf, &PathBuf> = all.iter().map(|(from, to)| (from, to)).collect();
+        let rename = |path: PathBuf| moved.get(&path).map_or(path, |to| (*to).clone());
+        let rename_source = |source: ImageSource| match source {
+            ImageSource::File(path) => ImageSource::File(rename(path)),
+            source => source,
+        };
+
+        self.images = std::mem::take(&mut self.images)
+            .into_iter()
+            .map(rename_source)
+            .collect();
+        self.marked = std::mem::take(&mut self.marked)
+            .into_iter()
+            .map(rename_source)
+            .collect();
+        self.broken_images = std::mem::take(&mut self.broken_images)
+            .into_iter()
+            .map(|(source, error)| (rename_source(source), error))
+            .collect();
+        self.page_sizes = std::mem::take(&mut self.page_sizes)
+            .into_iter()
+            .map(|(source, size)| (rename_source(source), size))
+            .collect();
+        self.raw_companions = std::mem::take(&mut self.raw_companions)
+            .into_iter()
+            .map(|(jpeg, raw)| (rename(jpeg), rename(raw)))
+            .collect();
+        Ok(())
+    }
+
+    /// Sorts the marked images, or the current one, into a triage folder,
+    /// taking them out of the list so the next image comes up.
+    fn triage(&mut self, folder: PathBuf) {
+        if self.target_paths().is_none() {
+            self.notification_manager
+                .add_error("Only files on disk can be moved or copied".to_string());
+            return;
+        }
+        let copy = self.config_manager.get_config().triage.copy;
+        self.transfer_images(Transfer {
+            folder,
+            copy,
+            dismiss: true,
+        });
+    }
+
+    /// Starts a move or copy, first asking what to do about files already in
+    /// the folder if the config says to.
+    fn transfer_images(&mut self, transfer: Transfer) {
+        let collision = Collision::from_name(&self.config_manager.get_config().files.on_collision)
+            .unwrap_or(Collision::Ask);
+        if collision == Collision::Ask {
+            let existing = self
+                .targets()
+                .iter()
+                .filter_map(|source| source.path())
+                .filter(|path| {
+                    fileops::target_in(path, &transfer.folder).is_ok_and(|target| target.exists())
+                })
+                .count();
+            if existing > 0 {
+                self.app_state = AppState::Collision { transfer, existing };
+                return;
+            }
+        }
+        self.finish_transfer(&transfer, collision);
+    }
+
+    /// Carries on with the move or copy that was waiting for an answer about
+    /// existing files.
+    fn resolve_collision(&mut self, collision: Collision) {
+        if let AppState::Collision { transfer, .. } =
+            std::mem::replace(&mut self.app_state, AppState::Normal)
+        {
+            self.finish_transfer(&transfer, collision);
+        }
+    }
+
+    /// Moves or copies the marked images, or the current one, creating the
+    /// folder if needed. A RAW file paired with a JPEG goes along with it,
+    /// and moved images leave the list.
+    fn finish_transfer(&mut self, transfer: &Transfer, collision: Collision) {
+        let Transfer { folder, copy, .. } = transfer;
+        let (verb, done) = if *copy {
+            ("copy", "Copied")
+        } else {
+            ("move", "Moved")
+        };
+        if let Err(e) = std::fs::create_dir_all(folder) {
+            self.notification_manager.add_error(format!(
+                "Failed to create {}: {}",
+                folder.display(),
+                e
+            ));
+            return;
+        }
+
+        let mut transferred = HashSet::new();
+        let mut skipped = 0;
+        let mut failures = Vec::new();
+        for source in self.targets() {
+            let Some(path) = source.path() else {
+                continue;
+            };
+            let target = match fileops::transfer(path, folder, *copy, collision) {
+                Ok(Some(target)) => target,
+                Ok(None) => {
+                    skipped += 1;
+                    continue;
+                }
+                Err(e) => {
+                    failures.push(format!("{}: {:#}", source.name(), e));
+                    continue;
+                }
+            };
+
+            // The RAW half keeps the JPEG's name, so they still pair up
+            if let Some(raw) = self.raw_companions.get(path) {
+                let raw_target = target.with_extension(raw.extension().unwrap_or_default());
+                let name = raw.file_name().unwrap_or_default().to_string_lossy();
+                if raw_target.exists() && collision != Collision::Overwrite {
+                    failures.push(format!("{}: {} already exists", name, raw_target.display()));
+                } else if let Err(e) = fileops::transfer_to(raw, &raw_target, *copy) {
+                    failures.push(format!("{}: {:#}", name, e));
+                }
+            }
+            transferred.insert(source);
+        }
+
+        if !transferred.is_empty() {
+            let what = match transferred.iter().next() {
+                Some(source) if transferred.len() == 1 => source.name(),
+                _ => format!("{} images", transferred.len()),
+            };
+            self.notification_manager.add_success(format!(
+                "{} {} to {}",
+                done,
+                what,
+                folder.display()
+            ));
+        }
+        if skipped > 0 {
+            self.notification_manager.add_info(format!(
+                "Skipped {} image(s) already in {}",
+                skipped,
+                folder.display()
+            ));
+        }
+        if let Some(failures) = summarize_failures(&failures) {
+            self.notification_manager
+                .add_error(format!("Failed to {} {}", verb, failures));
+        }
+        if !copy || transfer.dismiss {
+            self.remove_images(&transferred);
+        }
+    }
+
+    /// Rotates the marked images, or the current one, by a quarter turn and
+    /// saves them.
+    fn rotate_images(&mut self, clockwise: bool) {
+        let Some(paths) = self.target_paths() else {
+            self.notification_manager
+                .add_error("Only files on disk can be rotated".to_string());
+            return;
+        };
+
+        let mut rotated = Vec::new();
+        let mut failures = Vec::new();
+        for path in paths {
+            let result = fileops::rotate(&path, clockwise);
+            let source = ImageSource::File(path);
+            match result {
+                Ok(()) => {
+                    self.page_sizes.remove(&source);
+                    self.spread_starts.clear();
+                    rotated.push(source);
+                }
+                Err(e) => failures.push(format!("{}: {:#}", source.name(), e)),
+            }
+        }
+
+        let direction = if clockwise {
+            "clockwise"
+        } else {
+            "counterclockwise"
+        };
+        match rotated.as_slice() {
+            [] => {}
+            [source] => self.notification_manager.add_success(format!(
+                "Rotated {} {}",
+                source.name(),
+                direction
+            )),
+            _ => self.notification_manager.add_success(format!(
+                "Rotated {} images {}",
+                rotated.len(),
+                direction
+            )),
+        }
+        if let Some(failures) = summarize_failures(&failures) {
+            self.notification_manager
+                .add_error(format!("Failed to rotate {}", failures));
+        }
+
+        // Show the new orientation
+        let current = self.images.get(self.current_index);
+        if current.is_some_and(|current| rotated.contains(current))
+            || (!rotated.is_empty() && self.showing_spreads())
+        {
+            self.reload_spread();
+        }
+    }
+
+    /// Takes images that were moved or deleted out of the list, keeping the
+    /// current image selected if it is still there, or showing the one that
+    /// took its place.
+    fn remove_images(&mut self, gone: &HashSet<ImageSource>) {
+        if gone.is_empty() {
+            return;
+        }
+
+        let current_gone = self
+            .images
+            .get(self.current_index)
+            .is_some_and(|source| gone.contains(source));
+        let before = self.images[..self.current_index.min(self.images.len())]
+            .iter()
+            .filter(|source| gone.contains(*source))
+            .count();
+        self.images.retain(|source| !gone.contains(source));
+        self.spread_starts.clear();
+        for source in gone {
+            self.marked.remove(source);
+            self.broken_images.remove(source);
+            self.page_sizes.remove(source);
+            if let Some(path) = source.path() {
+                self.raw_companions.remove(path);
+            }
+        }
+
+        if self.images.is_empty() {
+            self.current_index = 0;
+            self.current_data = None;
+            self.load_error = None;
+            self.quads.clear();
+            return;
+        }
+        self.current_index = (self.current_index - before).min(self.images.len() - 1);
+
+        // One that fails to decode shows the placeholder, which is reported
+        // there rather than as a failed operation
+        if current_gone || self.showing_spreads() {
+            self.reload_spread();
+        }
+    }
+
+    /// Prints the marked paths for `--pick`, returning the exit code: 0 if
+    /// anything was picked, 1 if not.
+    fn print_marked(&self, separator: u8) -> i32 {
+        use std::io::Write;
+        use std::os::unix::ffi::OsStrExt;
+
+        let paths = self.marked_paths();
+        let mut stdout = std::io::stdout().lock();
+        for path in &paths {
+            let written = stdout
+                .write_all(path.as_os_str().as_bytes())
+                .and_then(|()| stdout.write_all(&[separator]));
+            if let Err(e) = written {
+                eprintln!("Failed to print marked paths: {}", e);
+                return 2;
+            }
+        }
+        let _ = stdout.flush();
+
+        if paths.is_empty() {
+            1
+        } else {
+            0
+        }
+    }
+
+    /// Sorts the images again after the order has changed, keeping the
+    /// current image selected.
+    fn resort(&mut self) {
+        let current = self.images.get(self.current_index).cloned();
+        self.sort_order.sort(&mut self.images);
+        self.spread_starts.clear();
+        if let Some(current) = current {
+            self.current_index = self
+                .images
+                .iter()
+                .position(|source| *source == current)
+                .unwrap_or(0);
+        }
+        // Spreads pair up differently in the new order
+        if self.showing_spreads() {
+            self.reload_spread();
+        }
+
+        self.notification_manager.add_info(format!(
+            "Sorted by {}{}",
+            self.sort_order.mode.description(),
+            if self.sort_order.descending && self.sort_order.mode != SortMode::Random {
+                ", descending"
+            } else {
+                ""
+            }
+        ));
+    }
+
+    fn cycle_sort(&mut self) {
+        self.sort_order.mode = self.sort_order.mode.next();
+        self.resort();
+    }
+
+    fn reverse_sort(&mut self) {
+        self.sort_order.descending = !self.sort_order.descending;
+        self.resort();
+    }
+
+    /// Jumps to the first image of the next or previous folder, wrapping
+    /// around at the ends. Images are sorted folder by folder, so each
+    /// folder's images are next to each other.
+    fn step_folder(&mut self, step: isize) -> Result<(String, (u32, u32))> {
+        if self.images.is_empty() {
+            return Err(anyhow!("No images loaded"));
+        }
+
+        let folder_start = |images: &[ImageSource], index: usize| {
+            let folder = images[index].folder();
+            (0..index)
+                .rev()
+                .find(|&i| images[i].folder() != folder)
+                .map_or(0, |i| i + 1)
+        };
+
+        let start = folder_start(&self.images, self.current_index);
+        let index = if step > 0 {
+            let folder = self.images[start].folder();
+            (start..self.images.len())
+                .find(|&i| self.images[i].folder() != folder)
+                .unwrap_or(0)
+        } else if start > 0 {
+            folder_start(&self.images, start - 1)
+        } else {
+            folder_start(&self.images, self.images.len() - 1)
+        };
+        if index == start {
+            return Err(anyhow!("All images are in the same folder"));
+        }
+
+        self.current_index = index;
+        if self.showing_spreads() {
+            self.current_index = self.spread_start(index);
+        }
+        self.load_image()
+    }
+
+    /// Whether navigation should stop at the image at `index`.
+    fn is_visible(&self, index: usize) -> bool {
+        !self.broken_filter || self.broken_images.contains_key(&self.images[index])
+    }
+
+    /// Moves `step` images forwards or backwards (wrapping) to the next image
+    /// that passes the broken-files filter, and loads it. When skipping broken
+    /// files is enabled, anything that fails to decode is stepped over too.
+    fn step_image(&mut self, step: isize) -> Result<(String, (u32, u32))> {
+        if self.images.is_empty() {
+            return Err(anyhow!("No images loaded"));
+        }
+        if self.showing_spreads() {
+            return self.step_spread(step);
+        }
+
+        let len = self.images.len() as isize;
+        let mut index = self.current_index;
+        for _ in 0..len {
+            index = (index as isize + step).rem_euclid(len) as usize;
+            if !self.is_visible(index) {
+                continue;
+            }
+
+            self.current_index = index;
+            match self.load_image() {
+                Err(e) if self.skip_broken() && !self.broken_filter => {
+                    self.notification_manager.add_warning(format!(
+                        "Skipped {}: {}",
+                        self.images[index].name(),
+                        e
+                    ));
+                }
+                result => return result,
+            }
+        }
+
+        Err(anyhow!(if self.broken_filter {
+            "No broken files found"
+        } else {
+            "None of the images could be decoded"
+        }))
+    }
+
+    fn skip_broken(&self) -> bool {
+        self.config_manager.get_config().files.skip_broken
+    }
+
+    /// Loads the image selected at startup. If it can't be decoded, the
+    /// placeholder is shown instead, or the next decodable image when
+    /// skipping broken files is enabled.
+    fn load_initial_image(&mut self) -> Result<(String, (u32, u32))> {
+        if self.showing_spreads() && !self.images.is_empty() {
+            self.current_index = self.spread_start(self.current_index);
+        }
+        match self.load_image() {
+            Err(e) if self.skip_broken() && self.images.len() > 1 => {
+                self.notification_manager.add_warning(format!(
+                    "Skipped {}: {}",
+                    self.images[self.current_index].name(),
+                    e
+                ));
+                self.step_image(1)
+            }
+            result => result,
+        }
+    }
+
+    fn next_image(&mut self) -> Result<(String, (u32, u32))> {
+        self.step_image(1)
+    }
+
+    fn prev_image(&mut self) -> Result<(String, (u32, u32))> {
+        self.step_image(-1)
+    }
+
+    /// Toggles showing only files that fail to decode. Turning it on checks
+    /// every image in the background so broken files can be found in bulk.
+    fn toggle_broken_filter(&mut self) {
+        self.broken_filter = !self.broken_filter;
+        if !self.broken_filter {
+            self.broken_scan = None;
+            self.notification_manager
+                .add_info("Showing all files".to_string());
+            return;
+        }
+
+        let sources = self.images.clone();
+        let options = formats::DecodeOptions {
+            page: 0,
+            ..self.decode_options()
+        };
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for source in sources {
+                let error = source
+                    .read()
+                    .and_then(|data| formats::decode_image(&data, &source, options))
+                    .err()
+                    .map(|e| e.to_string());
+                if sender.send((source, error)).is_err() {
+                    break;
+                }
+            }
+        });
+        self.broken_scan = Some(receiver);
+        self.notification_manager.add_info(format!(
+            "Checking {} images for broken files...",
+            self.images.len()
+        ));
+    }
+
+    /// Collects results from a running broken-file scan.
+    fn update_broken_scan(&mut self) {
+        let Some(receiver) = &self.broken_scan else {
+            return;
+        };
+
+        loop {
+            match receiver.try_recv() {
+                Ok((source, Some(error))) => {
+                    self.broken_images.insert(source, error);
+                }
+                Ok((source, None)) => {
+                    self.broken_images.remove(&source);
+                }
+                Err(mpsc::TryRecvError::Empty) => return,
+                Err(mpsc::TryRecvError::Disconnected) => break,
+            }
+        }
+
+        self.broken_scan = None;
+        let broken = self.broken_count();
+        if broken == 0 {
+            self.notification_manager
+                .add_success("No broken files found".to_string());
+        } else {
+            self.notification_manager
+                .add_warning(format!("Found {} broken file(s)", broken));
+            if !self.is_visible(self.current_index) {
+                let _ = self.next_image();
+            }
+        }
+    }
+
+    fn broken_count(&self) -> usize {
+        self.images
+            .iter()
+            .filter(|source| self.broken_images.contains_key(*source))
+            .count()
+    }
+
+    /// Sets the current image as the wallpaper or, with images marked,
+    /// rotates the wallpaper through them.
+    fn set_wallpaper(&mut self) -> Result<()> {
+        if self.images.is_empty() {
+            return Err(anyhow!("No images loaded"));
+        }
+
+        let Some(paths) = self.target_paths() else {
+            let error_msg = "Only files on disk can be set as the wallpaper".to_string();
+            self.notification_manager.add_error(error_msg.clone());
+            return Err(anyhow!(error_msg));
+        };
+        let paths = paths
+            .iter()
+            .map(|path| path.canonicalize())
+            .collect::<std::io::Result<Vec<_>>>()?;
+
+        // A rotation from before would soon replace the new wallpaper
+        wallpaper::stop_rotation();
+        self.apply_wallpaper(&paths[0])?;
+        if paths.len() < 2 {
+            return Ok(());
+        }
+
+        let interval = self
+            .config_manager
+            .get_config()
+            .wallpaper
+            .rotation_interval
+            .max(1);
+        if let Err(e) = wallpaper::start_rotation(&paths, interval) {
+            let error_msg = format!("{:#}", e);
+            self.notification_manager.add_error(error_msg.clone());
+            return Err(anyhow!(error_msg));
+        }
+        let every = if interval.is_multiple_of(60) {
+            format!("{} min", interval / 60)
+        } else {
+            format!("{} s", interval)
+        };
+        self.notification_manager.add_success(format!(
+            "Rotating wallpaper through {} images, every {}",
+            paths.len(),
+            every
+        ));
+        Ok(())
+    }
+
+    /// Carries out an action from the normal keybindings. Key presses and
+    /// the control socket both come through here.
+    fn perform_action(&mut self, action: &str) -> Result<()> {
+        match action {
+            "exit" => {
+                self.exit_requested = true;
+            }
+            "next_image" => {
+                let (title, _dimensions) = self.next_image()?;
+                self.notification_manager
+                    .add_info(format!("Next image: {}", title));
+            }
+            "previous_image" => {
+                let (title, _dimensions) = self.prev_image()?;
+                self.notification_manager
+                    .add_info(format!("Previous image: {}", title));
+            }
+            "set_wallpaper" => {
+                self.set_wallpaper()?;
+            }
+            "toggle_broken_filter" => {
+                self.toggle_broken_filter();
+            }
+            "next_page" | "previous_page" => {
+                let step = if action == "next_page" { 1 } else { -1 };
+                match self.step_page(step) {
+                    Ok(_) => {
+                        let message = format!("Page {}/{}", self.current_page + 1, self.page_count);
+                        self.notification_manager.add_info(message);
+                    }
+                    Err(e) => self.notification_manager.add_info(e.to_string()),
+                }
+            }
+            "exposure_up" | "exposure_down" | "gamma_up" | "gamma_down" | "reset_exposure" => {
+                let (exposure, gamma) = (self.exposure, self.gamma);
+                let (exposure, gamma) = match action {
+                    "exposure_up" => (exposure + EXPOSURE_STEP, gamma),
+                    "exposure_down" => (exposure - EXPOSURE_STEP, gamma),
+                    "gamma_up" => (exposure, gamma + GAMMA_STEP),
+                    "gamma_down" => (exposure, gamma - GAMMA_STEP),
+                    _ => (0.0, 1.0),
+                };
+                self.set_exposure(exposure, gamma);
+            }
+            "toggle_color_management" => {
+                self.toggle_color_management();
+            }
+            "toggle_recursive" => {
+                self.toggle_recursive();
+            }
+            "next_folder" | "previous_folder" => {
+                let step = if action == "next_folder" { 1 } else { -1 };
+                match self.step_folder(step) {
+                    Ok(_) => {
+                        let folder = self.current_folder().unwrap_or_else(|| ".".to_string());
+                        self.notification_manager
+                            .add_info(format!("Folder: {}", folder));
+                    }
+                    Err(e) => self.notification_manager.add_info(e.to_string()),
+                }
+            }
+            "toggle_mark" => {
+                self.toggle_mark();
+            }
+            "toggle_mark_all" => {
+                self.toggle_mark_all();
+            }
+            "invert_marks" => {
+                self.invert_marks();
+            }
+            "move_images" => {
+                self.open_prompt(PromptAction::MoveImages);
+            }
+            "copy_images" => {
+                self.open_prompt(PromptAction::CopyImages);
+            }
+            "rename_images" => self.open_rename(),
+            "paste_from_clipboard" => {
+                self.paste_from_clipboard();
+            }
+            "save_image_as" => self.open_save_prompt(),
+            "copy_image_to_clipboard" => {
+                self.copy_image_to_clipboard();
+            }
+            "copy_path_to_clipboard" => {
+                self.copy_path_to_clipboard(false);
+            }
+            "copy_uri_to_clipboard" => {
+                self.copy_path_to_clipboard(true);
+            }
+            "rotate_clockwise" | "rotate_counterclockwise" => {
+                let clockwise = action == "rotate_clockwise";
+                self.rotate_images(clockwise);
+            }
+            "toggle_follow_newest" => {
+                self.toggle_follow_newest();
+            }
+            "cycle_sort" => {
+                self.cycle_sort();
+            }
+            "reverse_sort" => {
+                self.reverse_sort();
+            }
+            "toggle_book_mode" => {
+                self.toggle_book_mode();
+            }
+            "toggle_reading_direction" => {
+                self.toggle_reading_direction();
+            }
+            "toggle_cover_offset" => {
+                self.toggle_cover_offset();
+            }
+            "zoom_in" => {
+                let zoom = self.zoom * ZOOM_STEP;
+                self.set_zoom(zoom);
+            }
+            "zoom_out" => {
+                let zoom = self.zoom / ZOOM_STEP;
+                self.set_zoom(zoom);
+            }
+            "reset_zoom" => {
+                self.set_zoom(1.0);
+            }
+            "delete_image" => {
+                if self.skip_delete_confirmation {
+                    if let Err(e) = self.delete_image() {
+                        self.notification_manager
+                            .add_error(format!("Failed to delete image: {}", e));
+                    }
+                } else {
+                    self.show_delete_confirmation();
+                    self.app_state = AppState::DeleteConfirmation;
+                }
+            }
+            _ => return Err(anyhow!("Unknown action '{}'", action)),
+        }
+        Ok(())
+    }
 
-        self.queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
-                rows_per_image: Some(dimensions.1),
-            },
-            texture_size,
-        );
+    /// Answers the requests that have come in on the control socket.
+    fn update_ipc(&mut self) {
+        let Some(server) = &self.ipc else {
+            return;
+        };
+        let requests: Vec<_> = std::iter::from_fn(|| server.try_recv()).collect();
+        for (request, reply) in requests {
+            let response = self.handle_request(request);
+            let _ = reply.send(response);
+        }
+    }
 
-        // Create texture view and bind group
-        let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("Texture Bind Group"),
-            layout: &self.texture_bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&texture_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&self.sampler),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 2,
-                    resource: self.uniform_buffer.as_entire_binding(),
-                },
-            ],
-        });
+    /// Answers one request from the control socket. Actions go through the
+    /// same dispatch as key presses, but are turned down while a prompt is
+    /// open.
+    fn handle_request(&mut self, request: ipc::Request) -> ipc::Response {
+        use ipc::{Request, Response, Zoom, ZoomStep};
 
-        self.texture_bind_group = Some(bind_group);
-        self.image_texture = Some(texture);
-        self.current_image_size = Some(dimensions);
+        let absolute =
+            |path: &Path| std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+        let result = match request {
+            Request::Path => {
+                return match self.images.get(self.current_index).map(ImageSource::path) {
+                    Some(Some(path)) => Response {
+                        path: Some(absolute(path)),
+                        ..Response::ok()
+                    },
+                    Some(None) => {
+                        Response::error(&anyhow!("The current image isn't a file of its own"))
+                    }
+                    None => Response::error(&anyhow!("No images loaded")),
+                };
+            }
+            Request::Index => {
+                if self.images.is_empty() {
+                    return Response::error(&anyhow!("No images loaded"));
+                }
+                return Response {
+                    index: Some(self.current_index + 1),
+                    count: Some(self.images.len()),
+                    ..Response::ok()
+                };
+            }
+            Request::Marks => {
+                let marks = self
+                    .images
+                    .iter()
+                    .filter(|source| self.marked.contains(*source))
+                    .filter_map(|source| source.path())
+                    .map(absolute)
+                    .collect();
+                return Response {
+                    marks: Some(marks),
+                    ..Response::ok()
+                };
+            }
+            _ if !matches!(self.app_state, AppState::Normal) => {
+                Err(anyhow!("EleViewr is waiting for an answer to a prompt"))
+            }
+            Request::Next => self.perform_action("next_image"),
+            Request::Prev => self.perform_action("previous_image"),
+            Request::Goto { index } => self.go_to(index),
+            Request::Open { path } => self.open_path(&path),
+            Request::Zoom {
+                level: Zoom::Factor(factor),
+            } => {
+                if factor.is_finite() && factor > 0.0 {
+                    self.set_zoom(factor);
+                    Ok(())
+                } else {
+                    Err(anyhow!("Invalid zoom factor {}", factor))
+                }
+            }
+            Request::Zoom {
+                level: Zoom::Step(step),
+            } => self.perform_action(match step {
+                ZoomStep::In => "zoom_in",
+                ZoomStep::Out => "zoom_out",
+                ZoomStep::Reset => "reset_zoom",
+            }),
+            Request::Quit => self.perform_action("exit"),
+            Request::SetWallpaper => self.perform_action("set_wallpaper"),
+            Request::Action { name } => match config::normal_action(&name) {
+                Some(action) => self.perform_action(action),
+                None => Err(anyhow!("Unknown action '{}'", name)),
+            },
+        };
 
-        // Update uniform buffer with new image dimensions
-        let screen_width = self.config.width as f32;
-        let screen_height = self.config.height as f32;
-        let screen_aspect = screen_width / screen_height;
+        match result {
+            Ok(()) => Response::ok(),
+            Err(e) => Response::error(&e),
+        }
+    }
 
-        let image_width = dimensions.0 as f32;
-        let image_height = dimensions.1 as f32;
-        let image_aspect = image_width / image_height;
+    /// Shows the image at a position in the list, counting from 1.
+    fn go_to(&mut self, index: usize) -> Result<()> {
+        if index == 0 || index > self.images.len() {
+            return Err(anyhow!(
+                "There is no image {} (the list has {})",
+                index,
+                self.images.len()
+            ));
+        }
+        self.current_index = index - 1;
+        self.reload_spread();
+        Ok(())
+    }
 
-        // Scale factor can be used for zoom operations (default to 1.0)
-        let scale_factor = 1.0;
+    /// Opens a file, folder or archive in place of what is being viewed, as
+    /// if it had been given on the command line.
+    fn open_path(&mut self, path: &Path) -> Result<()> {
+        if source::is_archive(path) {
+            self.load_archive(path)?;
+        } else if path.is_dir() || path.is_file() && formats::detect_file_format(path).is_some() {
+            self.load_images_in_directory(path)?;
+        } else if path.exists() {
+            return Err(anyhow!("Not an image: {}", path.display()));
+        } else {
+            return Err(anyhow!("File or directory not found: {}", path.display()));
+        }
+        self.load_initial_image()?;
+        Ok(())
+    }
 
-        let uniforms = Uniforms {
-            screen_aspect,
-            image_aspect,
-            scale_factor,
-            _padding: 0.0,
+    /// Runs a command from the config on the current image, and the marked
+    /// ones.
+    fn run_command(&mut self, command: &config::CommandSettings) -> Result<()> {
+        let current = self
+            .images
+            .get(self.current_index)
+            .and_then(|source| source.path())
+            .map(Path::to_path_buf);
+        let (Some(current), Some(targets)) = (current, self.target_paths()) else {
+            let error_msg = format!("Only files on disk can be opened with {}", command.name);
+            self.notification_manager.add_error(error_msg.clone());
+            return Err(anyhow!(error_msg));
         };
 
-        self.queue
-            .write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniforms]));
-
-        // Return the image name and dimensions
-        let title = format!(
-            "EleViewr - {}",
-            img_path.file_name().unwrap_or_default().to_string_lossy()
-        );
-
-        Ok((title, dimensions))
+        match commands::run(command, &current, &targets) {
+            Ok(Some(running)) => {
+                self.notification_manager
+                    .add_info(format!("Running {}", command.name));
+                self.running_commands.push(running);
+            }
+            Ok(None) => {
+                self.notification_manager
+                    .add_success(format!("Started {}", command.name));
+            }
+            Err(e) => {
+                let error_msg = format!("{:#}", e);
+                self.notification_manager.add_error(error_msg.clone());
+                return Err(anyhow!(error_msg));
+            }
+        }
+        Ok(())
     }
 
-    fn next_image(&mut self) -> Result<(String, (u32, u32))> {
-        if self.images.is_empty() {
-            return Err(anyhow!("No images loaded"));
+    /// Reports on commands that have finished, showing the images they
+    /// worked on again if asked to.
+    fn update_commands(&mut self) {
+        let mut reload = false;
+        for running in std::mem::take(&mut self.running_commands) {
+            match running.finished() {
+                None => self.running_commands.push(running),
+                Some(Ok(())) => {
+                    self.notification_manager
+                        .add_success(format!("{} finished", running.name));
+                    for path in running.reload {
+                        let source = ImageSource::File(path);
+                        self.broken_images.remove(&source);
+                        self.page_sizes.remove(&source);
+                        self.spread_starts.clear();
+                        reload = true;
+                    }
+                }
+                Some(Err(e)) => {
+                    self.notification_manager
+                        .add_error(format!("{} failed: {:#}", running.name, e));
+                }
+            }
         }
+        if reload {
+            self.reload_spread();
+        }
+    }
 
-        self.current_index = (self.current_index + 1) % self.images.len();
-        self.load_image()
+    /// Copies the current image to the clipboard as a PNG.
+    fn copy_image_to_clipboard(&mut self) {
+        let Some(source) = self.images.get(self.current_index).cloned() else {
+            return;
+        };
+        let result = self
+            .current_png(&source)
+            .and_then(|png| clipboard::copy(&png, "image/png"));
+        match result {
+            Ok(()) => self
+                .notification_manager
+                .add_success(format!("Copied {} to the clipboard", source.name())),
+            Err(e) => self
+                .notification_manager
+                .add_error(format!("Failed to copy image: {:#}", e)),
+        }
     }
 
-    fn prev_image(&mut self) -> Result<(String, (u32, u32))> {
-        if self.images.is_empty() {
-            return Err(anyhow!("No images loaded"));
+    /// The current image as PNG data: the file itself if it already is a
+    /// PNG, otherwise the page on screen encoded again.
+    fn current_png(&self, source: &ImageSource) -> Result<Vec<u8>> {
+        let data = match &self.current_data {
+            Some(data) => data.clone(),
+            None => source.read()?.into(),
+        };
+        let format = formats::detect_source_format(&data, source);
+        if format == Some(Format::Image(image::ImageFormat::Png)) && self.current_page == 0 {
+            return Ok(data.to_vec());
         }
 
-        self.current_index = if self.current_index == 0 {
-            self.images.len() - 1
-        } else {
-            self.current_index - 1
+        let img = formats::decode_image(&data, source, self.decode_options())?;
+        // PNG has no floating point samples, so HDR images go as 16-bit
+        let img = match img {
+            image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_) => {
+                image::DynamicImage::ImageRgba16(img.to_rgba16())
+            }
+            img => img,
         };
-        self.load_image()
+        let mut png = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut png, image::ImageOutputFormat::Png)?;
+        Ok(png.into_inner())
     }
 
-    fn set_wallpaper(&mut self) -> Result<()> {
-        if self.images.is_empty() {
-            return Err(anyhow!("No images loaded"));
-        }
+    /// Copies the current image's absolute path to the clipboard, as plain
+    /// text or as a `file://` URI that file managers and chat apps take as
+    /// the file itself.
+    fn copy_path_to_clipboard(&mut self, uri: bool) {
+        let Some(path) = self
+            .images
+            .get(self.current_index)
+            .and_then(|source| source.path())
+        else {
+            self.notification_manager
+                .add_error("Only files on disk have a path".to_string());
+            return;
+        };
 
-        let current_image = &self.images[self.current_index];
-        let image_path = current_image.canonicalize()?;
+        let result = path
+            .canonicalize()
+            .map_err(anyhow::Error::from)
+            .and_then(|path| {
+                let copied = if uri {
+                    clipboard::copy(clipboard::file_uri(&path).as_bytes(), "text/uri-list")
+                } else {
+                    clipboard::copy(
+                        path.as_os_str().as_encoded_bytes(),
+                        "text/plain;charset=utf-8",
+                    )
+                };
+                copied.map(|()| path)
+            });
+        match result {
+            Ok(path) => self.notification_manager.add_success(format!(
+                "Copied {} to the clipboard: {}",
+                if uri { "URI" } else { "path" },
+                path.display()
+            )),
+            Err(e) => self
+                .notification_manager
+                .add_error(format!("Failed to copy path: {:#}", e)),
+        }
+    }
 
+    /// Has hyprpaper load an image and show it on every monitor.
+    fn apply_wallpaper(&mut self, image_path: &Path) -> Result<()> {
         // First preload the image
         match Command::new("hyprctl")
             .args(["hyprpaper", "preload", &image_path.to_string_lossy()])
@@ -473,58 +3358,306 @@ impl ImageViewer {
         Ok(())
     }
 
+    /// Deletes the marked images, or the current one.
     fn delete_image(&mut self) -> Result<()> {
         if self.images.is_empty() {
             return Err(anyhow!("No images loaded"));
         }
+        if self.target_paths().is_none() {
+            return Err(anyhow!("Only files on disk can be deleted"));
+        }
 
-        let current_image = &self.images[self.current_index];
-        if current_image.exists() {
-            std::fs::remove_file(current_image)?;
-            self.notification_manager
-                .add_success(format!("Deleted image: {}", current_image.display()));
-
-            // Remove from the list and adjust index
-            self.images.remove(self.current_index);
-            if self.current_index >= self.images.len() && !self.images.is_empty() {
-                self.current_index = self.images.len() - 1; // Move to last image if we deleted beyond the end
-            } else if self.images.is_empty() {
-                self.current_index = 0;
+        let mut deleted = HashSet::new();
+        let mut message = String::new();
+        let mut failures = Vec::new();
+        for source in self.targets() {
+            let Some(path) = source.path() else {
+                continue;
+            };
+            if !path.exists() {
+                failures.push(format!("Image file does not exist: {}", path.display()));
+                continue;
+            }
+            if let Err(e) = std::fs::remove_file(path) {
+                failures.push(format!("{}: {}", path.display(), e));
+                continue;
             }
 
-            // Reload the next image
-            if !self.images.is_empty() {
-                self.load_image()?;
-            } else {
-                self.image_texture = None;
-                self.texture_bind_group = None;
-                self.current_image_size = None;
+            // A RAW+JPEG pair is one image, so both halves go
+            message = match self.raw_companions.get(path) {
+                Some(raw) => {
+                    if let Err(e) = std::fs::remove_file(raw) {
+                        failures.push(format!("{}: {}", raw.display(), e));
+                    }
+                    format!(
+                        "Deleted image: {} (+ {})",
+                        path.display(),
+                        raw.file_name().unwrap_or_default().to_string_lossy()
+                    )
+                }
+                None => format!("Deleted image: {}", path.display()),
+            };
+            deleted.insert(source);
+        }
+
+        match deleted.len() {
+            0 => {}
+            1 => self.notification_manager.add_success(message),
+            count => self
+                .notification_manager
+                .add_success(format!("Deleted {} images", count)),
+        }
+        self.remove_images(&deleted);
+
+        match summarize_failures(&failures) {
+            Some(failures) => Err(anyhow!(failures)),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Args {
+    /// Whether paths are read from a list rather than only given as
+    /// arguments.
+    fn lists_paths(&self) -> bool {
+        self.files_from.is_some() || self.paths.iter().any(|path| path == "-")
+    }
+
+    /// Whether `--descending` or `--ascending` asked for the order to be
+    /// reversed or not, overriding the config.
+    fn sort_direction(&self) -> Option<bool> {
+        match (self.descending, self.ascending) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        }
+    }
+}
+
+/// Sends a command to the running viewer for `eleviewr msg` and prints the
+/// answer to queries, returning the exit code: 0 on success, 1 if the viewer
+/// reported an error.
+fn send_message(command: &str, args: &[String], json: bool) -> Result<i32> {
+    let request = ipc::parse_message(command, args)?;
+    let response = ipc::send(&request)?;
+    if json {
+        println!("{}", serde_json::to_string(&response)?);
+    } else if let Some(error) = &response.error {
+        eprintln!("eleviewr: {}", error);
+    }
+    if !response.ok {
+        return Ok(1);
+    }
+    if json {
+        return Ok(0);
+    }
+
+    if let Some(path) = &response.path {
+        println!("{}", path.display());
+    }
+    if let (Some(index), Some(count)) = (response.index, response.count) {
+        println!("{}/{}", index, count);
+    }
+    for path in response.marks.iter().flatten() {
+        println!("{}", path.display());
+    }
+    Ok(0)
+}
+
+/// Sums up what went wrong in a batch operation: the first failure, and how
+/// many more there were.
+fn summarize_failures(failures: &[String]) -> Option<String> {
+    match failures {
+        [] => None,
+        [failure] => Some(failure.clone()),
+        [first, rest @ ..] => Some(format!("{} (and {} more)", first, rest.len())),
+    }
+}
+
+/// Gathers the paths to open from the arguments, expanding glob patterns,
+/// and from any path lists, along with an image piped to stdin in place of a
+/// list. With none given, the current directory is opened.
+fn collect_paths(args: &Args) -> Result<(Vec<PathBuf>, Option<MemoryImage>)> {
+    let mut paths = Vec::new();
+    let mut piped = None;
+    for arg in &args.paths {
+        if arg == "-" {
+            read_stdin(&mut paths, &mut piped)?;
+        } else if Path::new(arg).exists() {
+            paths.push(PathBuf::from(arg));
+        } else if arg.contains(['*', '?', '[']) {
+            let matches: Vec<PathBuf> = glob::glob(arg)
+                .map_err(|e| anyhow!("Invalid pattern '{}': {}", arg, e))?
+                .filter_map(|path| path.ok())
+                .collect();
+            if matches.is_empty() {
+                return Err(anyhow!("No files match: {}", arg));
             }
+            paths.extend(matches);
         } else {
-            return Err(anyhow!(
-                "Image file does not exist: {}",
-                current_image.display()
-            ));
+            return Err(anyhow!("File or directory not found: {}", arg));
         }
+    }
 
-        Ok(())
+    if let Some(file) = &args.files_from {
+        if file == Path::new("-") {
+            read_stdin(&mut paths, &mut piped)?;
+        } else {
+            let list = std::fs::read(file)
+                .map_err(|e| anyhow!("Cannot read {}: {}", file.display(), e))?;
+            paths.extend(parse_path_list(&list));
+        }
+    }
+
+    if paths.is_empty() && !args.lists_paths() {
+        paths.push(std::env::current_dir()?);
+    }
+    Ok((paths, piped))
+}
+
+/// Reads stdin, which holds either image data, as written by `grim -`, or a
+/// list of paths.
+fn read_stdin(paths: &mut Vec<PathBuf>, piped: &mut Option<MemoryImage>) -> Result<()> {
+    use std::io::Read;
+
+    let mut data = Vec::new();
+    std::io::stdin().lock().read_to_end(&mut data)?;
+    match formats::detect_format(&data, None) {
+        Some(format) => *piped = Some(MemoryImage::new(unsaved_name("stdin", format), data)),
+        None => paths.extend(parse_path_list(&data)),
+    }
+    Ok(())
+}
+
+/// A name to save an image piped in or pasted under, such as
+/// `clipboard-20240612-153000.png`.
+fn unsaved_name(origin: &str, format: Format) -> String {
+    format!(
+        "{}-{}.{}",
+        origin,
+        chrono::Local::now().format("%Y%m%d-%H%M%S"),
+        format.extension()
+    )
+}
+
+/// Parses a list of paths, one per line, or separated by NUL bytes as
+/// written by `find -print0` and `fd -0`.
+fn parse_path_list(data: &[u8]) -> Vec<PathBuf> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let separator = if data.contains(&0) { 0 } else { b'\n' };
+
+    data.split(|byte| *byte == separator)
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.is_empty())
+        .map(|line| PathBuf::from(std::ffi::OsStr::from_bytes(line)))
+        .collect()
+}
+
+/// Works out the sort order from the command line, falling back to the
+/// config. Without a seed, the random order is different every time.
+fn sort_order(args: &Args, files: &config::FileSettings) -> Result<sort::SortOrder> {
+    let name = args.sort.as_deref().unwrap_or(&files.sort);
+    let mode = SortMode::from_name(name).ok_or_else(|| {
+        anyhow!(
+            "Unknown sort order '{}' (expected one of: {})",
+            name,
+            SortMode::ALL.map(SortMode::name).join(", ")
+        )
+    })?;
+
+    let seed = match args.seed.unwrap_or(files.seed) {
+        0 => std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(1, |time| time.as_nanos() as u64),
+        seed => seed,
+    };
+
+    Ok(sort::SortOrder {
+        mode,
+        descending: args.sort_direction().unwrap_or(files.descending),
+        seed,
+    })
+}
+
+/// Matches each RAW file with a JPEG of the same name in the same directory,
+/// as written by cameras shooting RAW+JPEG. Returns the RAW for each JPEG.
+fn pair_raw_with_jpeg(found: &[(PathBuf, formats::Format)]) -> HashMap<PathBuf, PathBuf> {
+    let jpegs: HashMap<PathBuf, &PathBuf> = found
+        .iter()
+        .filter(|(_, format)| *format == formats::Format::Image(image::ImageFormat::Jpeg))
+        .map(|(path, _)| (path.with_extension(""), path))
+        .collect();
+
+    found
+        .iter()
+        .filter(|(_, format)| *format == formats::Format::Raw)
+        .filter_map(|(raw, _)| {
+            let jpeg = jpegs.get(&raw.with_extension(""))?;
+            Some(((*jpeg).clone(), raw.clone()))
+        })
+        .collect()
+}
+
+/// Prints every problem in the config files and returns the process exit code.
+fn check_config(source: &ConfigSource) -> Result<i32> {
+    let (checked, diagnostics) = ConfigManager::check_config(source)?;
+
+    if checked.is_empty() {
+        println!("No config files found, defaults will be used");
     }
+    for path in &checked {
+        println!("Checked {}", path.display());
+    }
+
+    if diagnostics.is_empty() {
+        println!("OK");
+        return Ok(0);
+    }
+
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    eprintln!("{} problem(s) found", diagnostics.len());
+    Ok(1)
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
-    // If no path is provided, use the current directory
-    let path = match &args.image_path {
-        Some(path_str) => {
-            let p = Path::new(path_str);
-            if !p.exists() {
-                return Err(anyhow!("File or directory not found: {}", path_str));
-            }
-            p.to_path_buf()
+    let config_source = ConfigSource::from_args(args.config.clone(), args.no_config);
+
+    if let Some(CliCommand::Msg {
+        command,
+        args: message_args,
+        json,
+    }) = &args.command
+    {
+        std::process::exit(send_message(command, message_args, *json)?);
+    }
+
+    if args.check_config {
+        std::process::exit(check_config(&config_source)?);
+    }
+
+    // A file asked for by name has to load; carrying on without it would
+    // quietly drop every other setting too
+    let mut notification_manager = NotificationManager::new();
+    let config_manager = match ConfigManager::new(&config_source) {
+        Ok(config_manager) => config_manager,
+        Err(e) if matches!(config_source, ConfigSource::File(_)) => return Err(e),
+        Err(e) => {
+            eprintln!("Failed to load config: {}. Using defaults.", e);
+            notification_manager.add_error(format!("Failed to load config: {}", e));
+            ConfigManager::default()
         }
-        None => std::env::current_dir()?,
     };
+    for diagnostic in config_manager.diagnostics() {
+        eprintln!("{}", diagnostic);
+        notification_manager.add_warning(diagnostic.to_string());
+    }
+
+    let (paths, piped) = collect_paths(&args)?;
 
     let event_loop = EventLoop::new();
 
@@ -606,7 +3739,7 @@ fn main() -> Result<()> {
                 },
                 wgpu::BindGroupLayoutEntry {
                     binding: 2,
-                    visibility: wgpu::ShaderStages::VERTEX,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                     ty: wgpu::BindingType::Buffer {
                         ty: wgpu::BufferBindingType::Uniform,
                         has_dynamic_offset: false,
@@ -675,98 +3808,107 @@ fn main() -> Result<()> {
         multiview: None,
     });
 
-    // Create uniform buffer for aspect ratio preservation
-    let initial_uniforms = Uniforms {
-        screen_aspect: size.width as f32 / size.height as f32,
-        image_aspect: 1.0, // Default to square
-        scale_factor: 1.0,
-        _padding: 0.0,
-    };
-
-    let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-        label: Some("Uniform Buffer"),
-        contents: bytemuck::cast_slice(&[initial_uniforms]),
-        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-    });
-
-    // Create uniform bind group
-    let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-        label: Some("Uniform Bind Group"),
-        layout: &texture_bind_group_layout,
-        entries: &[
-            wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::TextureView(
-                    &device
-                        .create_texture(&wgpu::TextureDescriptor {
-                            label: Some("Dummy Texture"),
-                            size: wgpu::Extent3d {
-                                width: 1,
-                                height: 1,
-                                depth_or_array_layers: 1,
-                            },
-                            mip_level_count: 1,
-                            sample_count: 1,
-                            dimension: wgpu::TextureDimension::D2,
-                            format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                            usage: wgpu::TextureUsages::TEXTURE_BINDING
-                                | wgpu::TextureUsages::COPY_DST,
-                            view_formats: &[],
-                        })
-                        .create_view(&wgpu::TextureViewDescriptor::default()),
-                ),
-            },
-            wgpu::BindGroupEntry {
-                binding: 1,
-                resource: wgpu::BindingResource::Sampler(&sampler),
-            },
-            wgpu::BindGroupEntry {
-                binding: 2,
-                resource: uniform_buffer.as_entire_binding(),
-            },
-        ],
-    });
-
     // Initialize egui
     let egui_ctx = egui::Context::default();
     let mut egui_state = egui_winit::State::new(&window);
     egui_state.set_pixels_per_point(window.scale_factor() as f32);
     let egui_renderer = egui_wgpu::Renderer::new(&device, surface_format, None, 1);
 
+    let book = config_manager.get_config().book.clone();
+    let sort_order = sort_order(&args, &config_manager.get_config().files)?;
+    let color_manager = color::ColorManager::new(&config_manager.get_config().color)
+        .unwrap_or_else(|e| {
+            eprintln!("{:#}", e);
+            notification_manager.add_error(format!("{:#}", e));
+            color::ColorManager::default()
+        });
+
     // Create the ImageViewer with the components we've initialized
     let viewer = Arc::new(Mutex::new(ImageViewer {
         images: Vec::new(),
         current_index: 0,
-        image_texture: None,
-        texture_bind_group: None,
+        root_dir: PathBuf::new(),
+        recursive: args.recursive || args.max_depth.is_some(),
+        max_depth: args.max_depth,
+        sort_order,
+        sort_requested: args.sort.is_some()
+            || args.sort_direction().is_some()
+            || args.seed.is_some(),
+        file_list: false,
+        marked: HashSet::new(),
+        last_destination: None,
+        memory_images: Vec::new(),
+        watcher: None,
+        watch_events: None,
+        follow_newest: args.follow,
+        current_data: None,
+        quads: Vec::new(),
         texture_bind_group_layout,
         device,
         queue,
         config,
         render_pipeline,
         sampler,
-        uniform_buffer,
-        uniform_bind_group,
-        current_image_size: None,
         app_state: AppState::Normal,
         skip_delete_confirmation: false,
         egui_ctx,
         egui_state,
         egui_renderer,
-        notification_manager: NotificationManager::new(),
-        config_manager: ConfigManager::new().unwrap_or_else(|e| {
-            eprintln!("Failed to load config: {}. Using defaults.", e);
-            ConfigManager::default()
-        }),
+        notification_manager,
+        config_manager,
+        load_error: None,
+        broken_images: HashMap::new(),
+        broken_filter: false,
+        broken_scan: None,
+        running_commands: Vec::new(),
+        ipc: None,
+        exit_requested: false,
+        raw_companions: HashMap::new(),
+        zoom: 1.0,
+        vector_image: false,
+        vector_stale: false,
+        current_page: 0,
+        page_count: 1,
+        exposure: 0.0,
+        gamma: 1.0,
+        color_manager,
+        book_mode: book.enabled,
+        right_to_left: book.right_to_left,
+        cover_offset: book.cover_offset,
+        page_sizes: HashMap::new(),
+        spread_starts: Vec::new(),
     }));
 
     // Load images from directory and update window
     {
         let mut viewer_lock = viewer.lock().unwrap();
-        viewer_lock.load_images_in_directory(&path)?;
+        // A single path is browsed along with its folder; several are shown
+        // as given
+        match paths.as_slice() {
+            // Only an image piped to stdin
+            [] if piped.is_some() => viewer_lock.file_list = true,
+            [path] if !args.lists_paths() && source::is_archive(path) => {
+                viewer_lock.load_archive(path)?
+            }
+            [path] if !args.lists_paths() => viewer_lock.load_images_in_directory(path)?,
+            paths => viewer_lock.load_paths(paths)?,
+        }
+        if let Some(image) = piped {
+            viewer_lock.add_memory_image(image);
+        }
+
+        // Listen for `eleviewr msg`
+        match ipc::Server::start() {
+            Ok(server) => viewer_lock.ipc = Some(server),
+            Err(e) => viewer_lock
+                .notification_manager
+                .add_warning(format!("{:#}", e)),
+        }
 
         // Load the first image and get its details
-        let (title, dimensions) = viewer_lock.load_image()?;
+        let (title, dimensions) = viewer_lock
+            .load_initial_image()
+            .unwrap_or_else(|_| (viewer_lock.window_title(), (0, 0)));
 
         // Update window title
         window.set_title(&title);
@@ -793,130 +3935,143 @@ fn main() -> Result<()> {
 
     // Run the event loop - this doesn't return, so we need to do our initialization before this
     // The Ok(()) return is not reachable
+    let pick = args.pick;
+    let separator = if args.null { 0 } else { b'\n' };
+    // Kept to only touch the window title when it changes
+    let mut window_title = String::new();
+
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
         match event {
-            Event::WindowEvent { window_id, event } => {
-                if window_id == win_id {
-                    // Handle egui events first
-                    {
+            Event::WindowEvent { window_id, event } if window_id == win_id => {
+                // Handle egui events first
+                {
+                    let mut viewer_lock = viewer.lock().unwrap();
+                    let ctx = viewer_lock.egui_ctx.clone();
+                    let response = viewer_lock.egui_state.on_event(&ctx, &event);
+                    // Only a prompt takes typing, but egui-winit reports Tab
+                    // as consumed all the same, which would swallow the sort
+                    // key. Enter and Escape still close the prompt.
+                    let keyboard = matches!(event, WindowEvent::KeyboardInput { .. });
+                    let typing = matches!(viewer_lock.app_state, AppState::Prompt(_));
+                    let closes_prompt = matches!(
+                        &event,
+                        WindowEvent::KeyboardInput { input, .. }
+                            if matches!(input.virtual_keycode, Some(VirtualKeyCode::Return | VirtualKeyCode::Escape))
+                    );
+                    if response.consumed && (!keyboard || typing && !closes_prompt) {
+                        return;
+                    }
+                }
+                match event {
+                    WindowEvent::CloseRequested => {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                    WindowEvent::Resized(physical_size) => {
                         let mut viewer_lock = viewer.lock().unwrap();
-                        let ctx = viewer_lock.egui_ctx.clone();
-                        let response = viewer_lock.egui_state.on_event(&ctx, &event);
-                        if response.consumed {
-                            return;
+                        if physical_size.width > 0 && physical_size.height > 0 {
+                            viewer_lock.config.width = physical_size.width;
+                            viewer_lock.config.height = physical_size.height;
+                            surface.configure(&viewer_lock.device, &viewer_lock.config);
+
+                            viewer_lock.update_uniforms();
+                            if viewer_lock.vector_image {
+                                viewer_lock.vector_stale = true;
+                            }
                         }
                     }
-                    match event {
-                        WindowEvent::CloseRequested => {
-                            *control_flow = ControlFlow::Exit;
-                        }
-                        WindowEvent::Resized(physical_size) => {
-                            let mut viewer_lock = viewer.lock().unwrap();
-                            if physical_size.width > 0 && physical_size.height > 0 {
-                                viewer_lock.config.width = physical_size.width;
-                                viewer_lock.config.height = physical_size.height;
-                                surface.configure(&viewer_lock.device, &viewer_lock.config);
-
-                                // Update uniform buffer with new screen aspect ratio
-                                if let Some(dimensions) = viewer_lock.current_image_size {
-                                    let screen_width = physical_size.width as f32;
-                                    let screen_height = physical_size.height as f32;
-                                    let screen_aspect = screen_width / screen_height;
-
-                                    let image_width = dimensions.0 as f32;
-                                    let image_height = dimensions.1 as f32;
-                                    let image_aspect = image_width / image_height;
-
-                                    let uniforms = Uniforms {
-                                        screen_aspect,
-                                        image_aspect,
-                                        scale_factor: 1.0,
-                                        _padding: 0.0,
-                                    };
-
-                                    viewer_lock.queue.write_buffer(
-                                        &viewer_lock.uniform_buffer,
-                                        0,
-                                        bytemuck::cast_slice(&[uniforms]),
-                                    );
+                    WindowEvent::KeyboardInput { input, .. } if input.state == ElementState::Pressed => {
+                        let mut viewer_lock = viewer.lock().unwrap();
+                        match viewer_lock.app_state {
+                            AppState::Normal => {
+                                if let Some(keycode) = input.virtual_keycode {
+                                    if let Some(action) = viewer_lock.config_manager.get_action_for_key(KeyContext::Normal, keycode).map(str::to_string) {
+                                        let _ = viewer_lock.perform_action(&action);
+                                    } else if let Some(folder) = viewer_lock.config_manager.get_triage_folder(keycode) {
+                                        viewer_lock.triage(folder);
+                                    } else if let Some(command) = viewer_lock.config_manager.get_command(keycode).cloned() {
+                                        let _ = viewer_lock.run_command(&command);
+                                    }
                                 }
                             }
-                        }
-                        WindowEvent::KeyboardInput { input, .. } => {
-                            if input.state == ElementState::Pressed {
-                                let mut viewer_lock = viewer.lock().unwrap();
-                                match viewer_lock.app_state {
-                                    AppState::Normal => {
-                                        if let Some(keycode) = input.virtual_keycode {
-                                            if let Some(action) = viewer_lock.config_manager.get_action_for_key(keycode) {
-                                                match action {
-                                                    "exit" => {
-                                                        *control_flow = ControlFlow::Exit;
-                                                    }
-                                                    "next_image" => {
-                                                        if let Ok((title, _dimensions)) = viewer_lock.next_image() {
-                                                            viewer_lock.notification_manager.add_info(format!("Next image: {}", title));
-                                                        }
-                                                    }
-                                                    "previous_image" => {
-                                                        if let Ok((title, _dimensions)) = viewer_lock.prev_image() {
-                                                            viewer_lock.notification_manager.add_info(format!("Previous image: {}", title));
-                                                        }
-                                                    }
-                                                    "set_wallpaper" => {
-                                                        let _ = viewer_lock.set_wallpaper();
-                                                    }
-                                                    "delete_image" => {
-                                                        if viewer_lock.skip_delete_confirmation {
-                                                            if let Err(e) = viewer_lock.delete_image() {
-                                                                viewer_lock.notification_manager.add_error(format!("Failed to delete image: {}", e));
-                                                            }
-                                                        } else {
-                                                            viewer_lock.show_delete_confirmation();
-                                                            viewer_lock.app_state = AppState::DeleteConfirmation;
-                                                        }
-                                                    }
-                                                    _ => {}
+                            AppState::DeleteConfirmation => {
+                                if let Some(keycode) = input.virtual_keycode {
+                                    if let Some(action) = viewer_lock.config_manager.get_action_for_key(KeyContext::DeleteConfirmation, keycode) {
+                                        match action {
+                                            "confirm_delete" => {
+                                                if let Err(e) = viewer_lock.delete_image() {
+                                                    viewer_lock.notification_manager.add_error(format!("Failed to delete image: {}", e));
                                                 }
+                                                viewer_lock.app_state = AppState::Normal;
                                             }
-                                        }
-                                    }
-                                    AppState::DeleteConfirmation => {
-                                        if let Some(keycode) = input.virtual_keycode {
-                                            if let Some(action) = viewer_lock.config_manager.get_action_for_key(keycode) {
-                                                match action {
-                                                    "confirm_delete" => {
-                                                        if let Err(e) = viewer_lock.delete_image() {
-                                                            viewer_lock.notification_manager.add_error(format!("Failed to delete image: {}", e));
-                                                        }
-                                                        viewer_lock.app_state = AppState::Normal;
-                                                    }
-                                                    "cancel_delete" => {
-                                                        viewer_lock.notification_manager.add_info("Delete cancelled.".to_string());
-                                                        viewer_lock.app_state = AppState::Normal;
-                                                    }
-                                                    "always_delete" => {
-                                                        viewer_lock.skip_delete_confirmation = true;
-                                                        if let Err(e) = viewer_lock.delete_image() {
-                                                            viewer_lock.notification_manager.add_error(format!("Failed to delete image: {}", e));
-                                                        }
-                                                        viewer_lock.app_state = AppState::Normal;
-                                                        viewer_lock.notification_manager.add_success("Delete confirmation disabled for this session.".to_string());
-                                                    }
-                                                    _ => {}
+                                            "cancel_delete" => {
+                                                viewer_lock.notification_manager.add_info("Delete cancelled.".to_string());
+                                                viewer_lock.app_state = AppState::Normal;
+                                            }
+                                            "always_delete" => {
+                                                viewer_lock.skip_delete_confirmation = true;
+                                                if let Err(e) = viewer_lock.delete_image() {
+                                                    viewer_lock.notification_manager.add_error(format!("Failed to delete image: {}", e));
                                                 }
+                                                viewer_lock.app_state = AppState::Normal;
+                                                viewer_lock.notification_manager.add_success("Delete confirmation disabled for this session.".to_string());
                                             }
+                                            _ => {}
                                         }
                                     }
                                 }
                             }
+                            AppState::Collision { .. } => {
+                                if let Some(keycode) = input.virtual_keycode {
+                                    let collision = match viewer_lock.config_manager.get_action_for_key(KeyContext::Collision, keycode) {
+                                        Some("skip_existing") => Some(Collision::Skip),
+                                        Some("rename_existing") => Some(Collision::Rename),
+                                        Some("overwrite_existing") => Some(Collision::Overwrite),
+                                        _ => None,
+                                    };
+                                    if let Some(collision) = collision {
+                                        viewer_lock.resolve_collision(collision);
+                                    }
+                                }
+                            }
+                            AppState::Prompt(_) => match input.virtual_keycode {
+                                Some(VirtualKeyCode::Return) => viewer_lock.submit_prompt(),
+                                Some(VirtualKeyCode::Escape) => {
+                                    viewer_lock.app_state = AppState::Normal;
+                                }
+                                _ => {}
+                            },
                         }
-                        _ => {}
                     }
+                    _ => {}
+                }
+            }
+            Event::LoopDestroyed => {
+                let mut viewer_lock = viewer.lock().unwrap();
+                // Remove the control socket
+                viewer_lock.ipc = None;
+                if pick {
+                    std::process::exit(viewer_lock.print_marked(separator));
                 }
             }
             Event::MainEventsCleared => {
+                {
+                    let mut viewer_lock = viewer.lock().unwrap();
+                    viewer_lock.update_broken_scan();
+                    viewer_lock.update_commands();
+                    viewer_lock.update_ipc();
+                    if viewer_lock.exit_requested {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                    viewer_lock.update_watch();
+                    viewer_lock.update_vector_render();
+
+                    let title = viewer_lock.window_title();
+                    if title != window_title {
+                        window.set_title(&title);
+                        window_title = title;
+                    }
+                }
                 window.request_redraw();
             }
             Event::RedrawRequested(_) => {
@@ -934,7 +4089,7 @@ fn main() -> Result<()> {
                 let (clipped_primitives, textures_delta, mut encoder) = {
                     let mut viewer_lock = viewer.lock().unwrap();
                     // Render UI and get primitives
-                    let (clipped_primitives, textures_delta) = viewer_lock.render_ui(window.inner_size());
+                    let (clipped_primitives, textures_delta) = viewer_lock.render_ui(&window);
                     let encoder = viewer_lock
                         .device
                         .create_command_encoder(&wgpu::CommandEncoderDescriptor {
@@ -968,14 +4123,12 @@ fn main() -> Result<()> {
 
                         render_pass.set_pipeline(&viewer_lock.render_pipeline);
 
-                        // Only draw the image if we have a bind group (i.e., an image loaded)
-                        if let Some(bind_group) = &viewer_lock.texture_bind_group {
-                            // Use the texture bind group for rendering
-                            render_pass.set_bind_group(0, bind_group, &[]);
-                            render_pass.draw(0..6, 0..1);
-                        } else {
-                            // Use the default uniform bind group if no image is loaded
-                            render_pass.set_bind_group(0, &viewer_lock.uniform_bind_group, &[]);
+                        // One quad per image of the spread. With no image
+                        // loaded (an empty folder or a broken file's
+                        // placeholder) nothing is drawn, so no checkerboard
+                        // shows behind the message.
+                        for quad in &viewer_lock.quads {
+                            render_pass.set_bind_group(0, &quad.bind_group, &[]);
                             render_pass.draw(0..6, 0..1);
                         }
                     }
@@ -1027,3 +4180,26 @@ fn main() -> Result<()> {
     #[allow(unreachable_code)]
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descending(flags: &[&str], config: bool) -> bool {
+        let args = Args::try_parse_from(["eleviewr"].iter().chain(flags)).unwrap();
+        let files = config::FileSettings {
+            descending: config,
+            ..Default::default()
+        };
+        sort_order(&args, &files).unwrap().descending
+    }
+
+    #[test]
+    fn sort_direction() {
+        assert!(!descending(&[], false));
+        assert!(descending(&[], true));
+        assert!(descending(&["--descending"], false));
+        assert!(!descending(&["--ascending"], true));
+        assert!(Args::try_parse_from(["eleviewr", "--ascending", "--descending"]).is_err());
+    }
+}
@@ -5,7 +5,6 @@ use std::time::{Duration, Instant};
 pub enum NotificationType {
     Info,
     Success,
-    #[allow(dead_code)]
     Warning,
     Error,
 }
@@ -93,7 +92,6 @@ impl NotificationManager {
         self.add_notification(Notification::new(message, NotificationType::Success));
     }
 
-    #[allow(dead_code)]
     pub fn add_warning(&mut self, message: String) {
         self.add_notification(Notification::new(message, NotificationType::Warning));
     }
@@ -0,0 +1,165 @@
+//! Pages within a single file: the pages of a multi-page TIFF and the
+//! sub-images of an ICO/CUR icon. The `image` crate only ever decodes one
+//! of these, so the rest are read here.
+
+use anyhow::{anyhow, Context, Result};
+use image::{DynamicImage, ImageBuffer};
+use std::io::Cursor;
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::ColorType;
+
+/// Counts the pages (image file directories) of a TIFF.
+pub fn tiff_page_count(data: &[u8]) -> usize {
+    let count = || -> Option<usize> {
+        let mut decoder = Decoder::new(Cursor::new(data)).ok()?;
+        let mut count = 1;
+        while decoder.more_images() {
+            decoder.next_image().ok()?;
+            count += 1;
+        }
+        Some(count)
+    };
+    count().unwrap_or(1)
+}
+
+/// The ICC profile of one page of a TIFF, since each page may have its own.
+#[cfg(feature = "icc")]
+pub fn tiff_icc_profile(data: &[u8], page: usize) -> Option<Vec<u8>> {
+    const ICC_PROFILE: tiff::tags::Tag = tiff::tags::Tag::Unknown(34675);
+
+    let mut decoder = Decoder::new(Cursor::new(data)).ok()?;
+    decoder.seek_to_image(page).ok()?;
+    decoder.get_tag_u8_vec(ICC_PROFILE).ok()
+}
+
+/// Decodes one page of a multi-page TIFF.
+pub fn decode_tiff_page(data: &[u8], page: usize) -> Result<DynamicImage> {
+    let mut decoder = Decoder::new(Cursor::new(data)).context("Failed to read TIFF file")?;
+    decoder
+        .seek_to_image(page)
+        .with_context(|| format!("TIFF file has no page {}", page + 1))?;
+
+    let (width, height) = decoder.dimensions()?;
+    let color_type = decoder.colortype()?;
+    let data = decoder.read_image().context("Failed to decode TIFF page")?;
+
+    let image = match (color_type, data) {
+        (ColorType::Gray(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma8)
+        }
+        (ColorType::Gray(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLuma16)
+        }
+        (ColorType::GrayA(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA8)
+        }
+        (ColorType::GrayA(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageLumaA16)
+        }
+        (ColorType::RGB(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8)
+        }
+        (ColorType::RGB(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb16)
+        }
+        (ColorType::RGB(32), DecodingResult::F32(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb32F)
+        }
+        (ColorType::RGBA(8), DecodingResult::U8(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba8)
+        }
+        (ColorType::RGBA(16), DecodingResult::U16(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba16)
+        }
+        (ColorType::RGBA(32), DecodingResult::F32(data)) => {
+            ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgba32F)
+        }
+        (ColorType::CMYK(8), DecodingResult::U8(data)) => {
+            let rgb = data
+                .chunks_exact(4)
+                .flat_map(|cmyk| {
+                    let k = 255 - cmyk[3] as u16;
+                    [0, 1, 2].map(|i| ((255 - cmyk[i] as u16) * k / 255) as u8)
+                })
+                .collect();
+            ImageBuffer::from_raw(width, height, rgb).map(DynamicImage::ImageRgb8)
+        }
+        (color_type, _) => {
+            return Err(anyhow!(
+                "Unsupported TIFF page color type: {:?}",
+                color_type
+            ))
+        }
+    };
+
+    image.ok_or_else(|| anyhow!("TIFF page data is truncated"))
+}
+
+/// One entry of an ICO/CUR directory.
+struct IconEntry {
+    /// The raw 16-byte directory entry
+    entry: [u8; 16],
+    data: Vec<u8>,
+}
+
+impl IconEntry {
+    fn bits_per_pixel(&self) -> u16 {
+        u16::from_le_bytes([self.entry[6], self.entry[7]])
+    }
+
+    fn area(&self) -> u32 {
+        // A stored size of 0 means 256
+        let size = |byte: u8| if byte == 0 { 256 } else { byte as u32 };
+        size(self.entry[0]) * size(self.entry[1])
+    }
+}
+
+/// Reads the sub-images of an icon, best first, in the order the `image`
+/// crate would pick them: by bit depth, then by size.
+fn icon_entries(data: &[u8]) -> Result<Vec<IconEntry>> {
+    let count = data
+        .get(4..6)
+        .map(|count| u16::from_le_bytes([count[0], count[1]]) as usize)
+        .ok_or_else(|| anyhow!("Icon file is truncated"))?;
+
+    let mut entries = (0..count)
+        .map(|i| {
+            let entry: [u8; 16] = data
+                .get(6 + i * 16..22 + i * 16)
+                .and_then(|entry| entry.try_into().ok())?;
+            let length = u32::from_le_bytes(entry[8..12].try_into().ok()?) as usize;
+            let offset = u32::from_le_bytes(entry[12..16].try_into().ok()?) as usize;
+            let data = data.get(offset..offset.checked_add(length)?)?.to_vec();
+            Some(IconEntry { entry, data })
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| anyhow!("Icon file is truncated"))?;
+
+    entries.sort_by_key(|entry| std::cmp::Reverse((entry.bits_per_pixel(), entry.area())));
+    Ok(entries)
+}
+
+/// Counts the sub-images of an ICO/CUR file.
+pub fn icon_page_count(data: &[u8]) -> usize {
+    icon_entries(data).map_or(1, |entries| entries.len().max(1))
+}
+
+/// Decodes one sub-image of an ICO/CUR file by wrapping it in an icon of
+/// its own, so the `image` crate's decoder can handle both the PNG and BMP
+/// flavours of entry.
+pub fn decode_icon_page(data: &[u8], page: usize) -> Result<DynamicImage> {
+    let entries = icon_entries(data)?;
+    let IconEntry { mut entry, data } = entries
+        .into_iter()
+        .nth(page)
+        .ok_or_else(|| anyhow!("Icon file has no image {}", page + 1))?;
+
+    // The single entry's data follows the 6-byte header and 16-byte entry
+    entry[12..16].copy_from_slice(&22u32.to_le_bytes());
+    let mut icon = vec![0, 0, 1, 0, 1, 0];
+    icon.extend_from_slice(&entry);
+    icon.extend_from_slice(&data);
+
+    image::load_from_memory_with_format(&icon, image::ImageFormat::Ico)
+        .context("Failed to decode icon image")
+}
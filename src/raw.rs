@@ -0,0 +1,161 @@
+//! Camera RAW support: the camera's embedded JPEG preview for fast culling,
+//! or a full demosaic through imagepipe/rawloader when asked for.
+
+use anyhow::{anyhow, Context, Result};
+use image::DynamicImage;
+use std::io::Cursor;
+
+/// Decodes a RAW file. The embedded preview is used unless `full_decode` is
+/// set or the file doesn't carry one.
+pub fn decode(data: &[u8], full_decode: bool) -> Result<DynamicImage> {
+    if !full_decode {
+        if let Some(preview) = largest_embedded_jpeg(data) {
+            let image = image::load_from_memory_with_format(preview, image::ImageFormat::Jpeg)
+                .context("Failed to decode embedded RAW preview")?;
+            return Ok(apply_orientation(image, tiff_orientation(data)));
+        }
+    }
+
+    // imagepipe applies the camera's orientation itself
+    let raw = rawloader::decode(&mut Cursor::new(data))
+        .map_err(|e| anyhow!("Failed to decode RAW file: {}", e))?;
+    let decoded = imagepipe::Pipeline::new_from_source(imagepipe::ImageSource::Raw(raw))
+        .and_then(|mut pipeline| pipeline.output_8bit(None))
+        .map_err(|e| anyhow!("Failed to decode RAW file: {}", e))?;
+    let buffer =
+        image::RgbImage::from_raw(decoded.width as u32, decoded.height as u32, decoded.data)
+            .ok_or_else(|| anyhow!("RAW image data is truncated"))?;
+    Ok(DynamicImage::ImageRgb8(buffer))
+}
+
+/// Finds the biggest baseline or progressive JPEG stream embedded in the
+/// file, which is the full-size preview in every common RAW container (the
+/// sensor data itself is either not JPEG or lossless JPEG, which is skipped).
+fn largest_embedded_jpeg(data: &[u8]) -> Option<&[u8]> {
+    let mut largest: Option<&[u8]> = None;
+    let mut pos = 0;
+
+    while let Some(offset) = find(&data[pos..], &[0xff, 0xd8, 0xff]) {
+        let start = pos + offset;
+        match jpeg_end(data, start) {
+            Some(end) => {
+                if largest.is_none_or(|jpeg| end - start > jpeg.len()) {
+                    largest = Some(&data[start..end]);
+                }
+                pos = end;
+            }
+            None => pos = start + 1,
+        }
+    }
+
+    largest
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Walks the JPEG's marker segments from the SOI at `start`, returning the
+/// offset just past its EOI if it is a well-formed, lossy JPEG.
+fn jpeg_end(data: &[u8], start: usize) -> Option<usize> {
+    let mut pos = start + 2;
+    let mut lossy_frame = false;
+
+    loop {
+        if *data.get(pos)? != 0xff {
+            return None;
+        }
+        // Markers may be preceded by any number of fill bytes
+        while *data.get(pos + 1)? == 0xff {
+            pos += 1;
+        }
+        let marker = *data.get(pos + 1)?;
+        pos += 2;
+
+        match marker {
+            0xd9 => return lossy_frame.then_some(pos),
+            0x01 | 0xd0..=0xd7 => continue,
+            _ => {}
+        }
+
+        let length = u16::from_be_bytes([*data.get(pos)?, *data.get(pos + 1)?]) as usize;
+        if length < 2 {
+            return None;
+        }
+        match marker {
+            // Baseline, extended and progressive Huffman frames
+            0xc0..=0xc2 => lossy_frame = true,
+            // Lossless and arithmetic-coded frames are sensor data or
+            // something the decoder can't handle
+            0xc3 | 0xc5..=0xc7 | 0xc9..=0xcb | 0xcd..=0xcf => return None,
+            _ => {}
+        }
+        pos += length;
+
+        if marker == 0xda {
+            // Skip entropy-coded data up to the next real marker
+            loop {
+                let next = pos + find(&data[pos..], &[0xff])?;
+                match *data.get(next + 1)? {
+                    0x00 | 0xd0..=0xd7 | 0xff => pos = next + 1,
+                    _ => {
+                        pos = next;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Reads the Orientation tag from IFD0 of a TIFF-based RAW (CR2, NEF, ARW,
+/// DNG, ...). Previews are stored unrotated, so this has to be applied.
+fn tiff_orientation(data: &[u8]) -> u16 {
+    let read_u16 = |bytes: &[u8], little_endian: bool| {
+        let bytes = [bytes[0], bytes[1]];
+        if little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        }
+    };
+
+    let parse = || -> Option<u16> {
+        let little_endian = match data.get(0..4)? {
+            [b'I', b'I', 42, 0] => true,
+            [b'M', b'M', 0, 42] => false,
+            _ => return None,
+        };
+        let offset_bytes: [u8; 4] = data.get(4..8)?.try_into().ok()?;
+        let ifd = if little_endian {
+            u32::from_le_bytes(offset_bytes)
+        } else {
+            u32::from_be_bytes(offset_bytes)
+        } as usize;
+
+        let count = read_u16(data.get(ifd..ifd + 2)?, little_endian) as usize;
+        (0..count).find_map(|i| {
+            let entry = data.get(ifd + 2 + i * 12..ifd + 14 + i * 12)?;
+            (read_u16(&entry[0..2], little_endian) == 0x0112)
+                .then(|| read_u16(&entry[8..10], little_endian))
+        })
+    };
+
+    parse().unwrap_or(1)
+}
+
+/// Applies an EXIF/TIFF orientation value (1-8).
+fn apply_orientation(image: DynamicImage, orientation: u16) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
@@ -0,0 +1,453 @@
+//! Renaming images: checking that a set of new names can all be used, and
+//! batch renaming with templates such as `{date:%Y%m%d}_{n:04}{ext}`.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime};
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::sort;
+use crate::source::ImageSource;
+
+/// What `{date}` looks like without a format of its own.
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// The widest `{n:...}` counter, far more digits than any batch needs.
+const MAX_COUNTER_WIDTH: usize = 32;
+
+enum Field {
+    Text(String),
+    /// The original name without its extension
+    Name,
+    /// The original extension with its dot, or nothing
+    Extension,
+    /// The image's place in the batch, counting from 1, zero-padded to a width
+    Counter(usize),
+    /// When the photo was taken, in strftime format
+    Date(String),
+}
+
+pub struct Template {
+    fields: Vec<Field>,
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self> {
+        let mut fields = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                fields.push(Field::Text(rest[..start].to_string()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .map(|end| start + end)
+                .ok_or_else(|| anyhow!("'{{' without a closing '}}'"))?;
+
+            let field = &rest[start + 1..end];
+            fields.push(match field.split_once(':') {
+                None if field == "name" => Field::Name,
+                None if field == "ext" => Field::Extension,
+                None if field == "n" => Field::Counter(0),
+                None if field == "date" => Field::Date(DATE_FORMAT.to_string()),
+                Some(("n", width)) => {
+                    let width: usize = width
+                        .parse()
+                        .map_err(|_| anyhow!("invalid counter width '{}'", width))?;
+                    if width > MAX_COUNTER_WIDTH {
+                        return Err(anyhow!(
+                            "counter width {} is over {}",
+                            width,
+                            MAX_COUNTER_WIDTH
+                        ));
+                    }
+                    Field::Counter(width)
+                }
+                Some(("date", format)) => {
+                    if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                        return Err(anyhow!("invalid date format '{}'", format));
+                    }
+                    Field::Date(format.to_string())
+                }
+                _ => return Err(anyhow!("unknown field '{{{}}}'", field)),
+            });
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            fields.push(Field::Text(rest.to_string()));
+        }
+        Ok(Self { fields })
+    }
+
+    /// The new file name for the `n`th image of a batch, counting from 1.
+    pub fn render(&self, original: &Original, n: usize) -> String {
+        let path = &original.path;
+        self.fields
+            .iter()
+            .map(|field| match field {
+                Field::Text(text) => text.clone(),
+                Field::Name => path
+                    .file_stem()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+                Field::Extension => path
+                    .extension()
+                    .map(|extension| format!(".{}", extension.to_string_lossy()))
+                    .unwrap_or_default(),
+                Field::Counter(width) => format!("{:0width$}", n, width = *width),
+                Field::Date(format) => original
+                    .date
+                    .map(|date| date.format(format).to_string())
+                    .unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+/// An image in a batch rename, with the date its template may use.
+#[derive(Debug, Clone)]
+pub struct Original {
+    pub path: PathBuf,
+    /// When the photo was taken according to its EXIF data, or when the
+    /// file was last modified
+    pub date: Option<NaiveDateTime>,
+}
+
+impl Original {
+    pub fn new(path: PathBuf) -> Self {
+        let taken = sort::exif_datetime(&ImageSource::File(path.clone())).and_then(|date| {
+            NaiveDate::from_ymd_opt(date.year as i32, date.month as u32, date.day as u32)?
+                .and_hms_opt(date.hour as u32, date.minute as u32, date.second as u32)
+        });
+        let modified = || {
+            let modified = path.metadata().ok()?.modified().ok()?;
+            Some(DateTime::<Local>::from(modified).naive_local())
+        };
+        let date = taken.or_else(modified);
+        Self { path, date }
+    }
+}
+
+/// A batch rename being typed in, with the names its template gives so far.
+#[derive(Clone)]
+pub struct BatchRename {
+    pub originals: Arc<[Original]>,
+    /// The template `names` were worked out for
+    template: Option<String>,
+    /// The new paths in the same order as `originals`, or why the template
+    /// can't be used
+    pub names: Result<Arc<[PathBuf]>, String>,
+}
+
+impl BatchRename {
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        Self {
+            originals: paths.into_iter().map(Original::new).collect(),
+            template: None,
+            names: Err(String::new()),
+        }
+    }
+
+    /// Works out the new names again if the template has changed.
+    pub fn update(&mut self, template: &str) {
+        if self.template.as_deref() == Some(template) {
+            return;
+        }
+        self.template = Some(template.to_string());
+        self.names = self
+            .plan(template)
+            .map(Arc::from)
+            .map_err(|e| format!("{:#}", e));
+    }
+
+    fn plan(&self, template: &str) -> Result<Vec<PathBuf>> {
+        let template = Template::parse(template)?;
+        let names = self
+            .originals
+            .iter()
+            .enumerate()
+            .map(|(i, original)| {
+                let name = template.render(original, i + 1);
+                original.path.with_file_name(name)
+            })
+            .collect::<Vec<_>>();
+
+        let renames: Vec<_> = self
+            .originals
+            .iter()
+            .map(|original| original.path.clone())
+            .zip(names.iter().cloned())
+            .collect();
+        check(&renames)?;
+        Ok(names)
+    }
+}
+
+/// Checks that a set of renames can all be made: every new name is a plain
+/// file name, no two images end up with the same one, and nothing outside
+/// the set is replaced.
+pub fn check(renames: &[(PathBuf, PathBuf)]) -> Result<()> {
+    let renamed: HashSet<&Path> = renames.iter().map(|(from, _)| from.as_path()).collect();
+    let mut taken: HashMap<&Path, &Path> = HashMap::new();
+
+    for (from, to) in renames {
+        let from_name = from.file_name().unwrap_or_default().to_string_lossy();
+        let name = to
+            .file_name()
+            .filter(|name| to.parent() == from.parent() && !name.is_empty())
+            .filter(|name| !matches!(name.to_str(), Some("." | "..")))
+            .ok_or_else(|| anyhow!("{} would get an unusable name", from_name))?;
+
+        if let Some(other) = taken.insert(to, from) {
+            return Err(anyhow!(
+                "{} and {} would both be named {}",
+                other.file_name().unwrap_or_default().to_string_lossy(),
+                from_name,
+                name.to_string_lossy()
+            ));
+        }
+        if to.exists() && !renamed.contains(to.as_path()) {
+            return Err(anyhow!("{} already exists", name.to_string_lossy()));
+        }
+    }
+    Ok(())
+}
+
+/// Renames a set of files that has been through `check`. Everything is
+/// first moved to a temporary name, so images can swap names or take each
+/// other's, and any failure puts back what was already renamed.
+pub fn apply(renames: &[(PathBuf, PathBuf)]) -> Result<()> {
+    let renames: Vec<_> = renames.iter().filter(|(from, to)| from != to).collect();
+
+    // Renaming replaces whatever has the name already, so each temporary
+    // name has to be free: not a file, such as one left by an interrupted
+    // rename, nor a new name or another temporary one
+    let targets: HashSet<&Path> = renames.iter().map(|(_, to)| to.as_path()).collect();
+    let mut temporaries: Vec<PathBuf> = Vec::new();
+    for (from, _) in &renames {
+        let mut attempt = 0;
+        let temporary = loop {
+            let temporary = temporary_path(from, attempt);
+            let free = !targets.contains(temporary.as_path())
+                && !temporaries.contains(&temporary)
+                && fs::symlink_metadata(&temporary).is_err();
+            if free {
+                break temporary;
+            }
+            attempt += 1;
+        };
+        temporaries.push(temporary);
+    }
+
+    for (i, ((from, _), temporary)) in renames.iter().zip(&temporaries).enumerate() {
+        if let Err(e) = fs::rename(from, temporary) {
+            for ((from, _), temporary) in renames[..i].iter().zip(&temporaries) {
+                let _ = fs::rename(temporary, from);
+            }
+            return Err(e).with_context(|| format!("Failed to rename {}", from.display()));
+        }
+    }
+
+    for (i, ((_, to), temporary)) in renames.iter().zip(&temporaries).enumerate() {
+        if let Err(e) = fs::rename(temporary, to) {
+            for ((_, to), temporary) in renames[..i].iter().zip(&temporaries) {
+                let _ = fs::rename(to, temporary);
+            }
+            for ((from, _), temporary) in renames.iter().zip(&temporaries) {
+                let _ = fs::rename(temporary, from);
+            }
+            return Err(e).with_context(|| format!("Failed to rename to {}", to.display()));
+        }
+    }
+    Ok(())
+}
+
+/// The `attempt`th name to try for `path` while it is being renamed.
+fn temporary_path(path: &Path, attempt: usize) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    if attempt > 0 {
+        name.push(format!(".{}", attempt));
+    }
+    name.push(".renaming");
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(template: &str, path: &str, n: usize) -> String {
+        let original = Original {
+            path: PathBuf::from(path),
+            date: NaiveDate::from_ymd_opt(2024, 3, 9).and_then(|date| date.and_hms_opt(14, 5, 30)),
+        };
+        Template::parse(template).unwrap().render(&original, n)
+    }
+
+    fn parse_error(template: &str) -> String {
+        match Template::parse(template) {
+            Ok(_) => panic!("'{}' parsed", template),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse_error("img_{n"), "'{' without a closing '}'");
+        assert_eq!(parse_error("{size}"), "unknown field '{size}'");
+        assert_eq!(parse_error("{name:x}"), "unknown field '{name:x}'");
+        assert_eq!(parse_error("{n:-1}"), "invalid counter width '-1'");
+        assert_eq!(parse_error("{n:}"), "invalid counter width ''");
+        assert_eq!(parse_error("{n:33}"), "counter width 33 is over 32");
+        assert_eq!(
+            parse_error("{n:99999999999999999999}"),
+            "invalid counter width '99999999999999999999'"
+        );
+        assert_eq!(parse_error("{date:%Q}"), "invalid date format '%Q'");
+    }
+
+    #[test]
+    fn fields() {
+        assert_eq!(render("{name}{ext}", "/a/photo.tar.gz", 1), "photo.tar.gz");
+        assert_eq!(render("{name}-copy{ext}", "/a/README", 1), "README-copy");
+        assert_eq!(render("plain", "/a/b.png", 1), "plain");
+    }
+
+    #[test]
+    fn counter_padding() {
+        assert_eq!(render("{n}", "/a/b.png", 7), "7");
+        assert_eq!(render("{n:03}", "/a/b.png", 7), "007");
+        assert_eq!(render("{n:2}", "/a/b.png", 123), "123");
+        assert_eq!(render("{n:32}", "/a/b.png", 1).len(), 32);
+    }
+
+    #[test]
+    fn date_formats() {
+        assert_eq!(render("{date}", "/a/b.png", 1), "2024-03-09");
+        assert_eq!(
+            render("{date:%Y%m%d_%H%M%S}_{n:02}{ext}", "/a/b.png", 3),
+            "20240309_140530_03.png"
+        );
+        let undated = Original {
+            path: PathBuf::from("/a/b.png"),
+            date: None,
+        };
+        let template = Template::parse("x{date}{ext}").unwrap();
+        assert_eq!(template.render(&undated, 1), "x.png");
+    }
+
+    fn renames(dir: &Path, pairs: &[(&str, &str)]) -> Vec<(PathBuf, PathBuf)> {
+        pairs
+            .iter()
+            .map(|(from, to)| (dir.join(from), dir.join(to)))
+            .collect()
+    }
+
+    fn write(dir: &Path, names: &[&str]) {
+        for name in names {
+            fs::write(dir.join(name), name).unwrap();
+        }
+    }
+
+    #[test]
+    fn collisions_within_the_set() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), &["a.png", "b.png"]);
+        let error = check(&renames(
+            dir.path(),
+            &[("a.png", "c.png"), ("b.png", "c.png")],
+        ))
+        .unwrap_err()
+        .to_string();
+        assert_eq!(error, "a.png and b.png would both be named c.png");
+    }
+
+    #[test]
+    fn collisions_with_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), &["a.png", "b.png", "other.png"]);
+        let error = check(&renames(dir.path(), &[("a.png", "other.png")]))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(error, "other.png already exists");
+
+        // Taking the name of an image that is itself being renamed is fine
+        check(&renames(
+            dir.path(),
+            &[("a.png", "b.png"), ("b.png", "c.png")],
+        ))
+        .unwrap();
+    }
+
+    #[test]
+    fn unusable_names() {
+        let dir = tempfile::tempdir().unwrap();
+        for to in ["", ".", "..", "sub/a.png"] {
+            let renames = [(dir.path().join("a.png"), dir.path().join(to))];
+            assert!(check(&renames).is_err(), "'{}' was allowed", to);
+        }
+    }
+
+    #[test]
+    fn swaps() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), &["a.png", "b.png", "c.png"]);
+        let renames = renames(
+            dir.path(),
+            &[("a.png", "b.png"), ("b.png", "c.png"), ("c.png", "a.png")],
+        );
+        check(&renames).unwrap();
+        apply(&renames).unwrap();
+
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("a.png"), "c.png");
+        assert_eq!(read("b.png"), "a.png");
+        assert_eq!(read("c.png"), "b.png");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn leftover_temporary_files_survive() {
+        let dir = tempfile::tempdir().unwrap();
+        write(
+            dir.path(),
+            &["a.png", "b.png", ".a.png.renaming", ".a.png.1.renaming"],
+        );
+        let renames = renames(
+            dir.path(),
+            &[("a.png", "b.png"), ("b.png", ".b.png.renaming")],
+        );
+        check(&renames).unwrap();
+        apply(&renames).unwrap();
+
+        let read = |name: &str| fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("b.png"), "a.png");
+        assert_eq!(read(".b.png.renaming"), "b.png");
+        assert_eq!(read(".a.png.renaming"), ".a.png.renaming");
+        assert_eq!(read(".a.png.1.renaming"), ".a.png.1.renaming");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 4);
+    }
+
+    #[test]
+    fn batch_plan() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), &["x.png", "y.jpg"]);
+        let mut batch = BatchRename::new(vec![dir.path().join("x.png"), dir.path().join("y.jpg")]);
+
+        batch.update("img_{n:02}{ext}");
+        let names = batch.names.clone().unwrap();
+        assert_eq!(
+            names.as_ref(),
+            [dir.path().join("img_01.png"), dir.path().join("img_02.jpg")]
+        );
+
+        batch.update("same");
+        assert!(batch.names.is_err());
+    }
+}
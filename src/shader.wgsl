@@ -4,11 +4,20 @@ struct VertexOutput {
     @location(0) tex_coords: vec2<f32>,
 };
 
-// Uniform buffer for aspect ratio preservation
+// Uniform buffer for aspect ratio preservation and display adjustments
 struct Uniforms {
     screen_aspect: f32,
     image_aspect: f32,
     scale_factor: f32,
+    // Exposure adjustment in stops
+    exposure: f32,
+    // Display gamma adjustment, 1.0 for none
+    gamma: f32,
+    // 1.0 when the image holds linear HDR values that need tone mapping
+    tone_map: f32,
+    // Horizontal extent of this quad within the spread, from -1.0 to 1.0
+    quad_left: f32,
+    quad_right: f32,
 };
 
 @group(0) @binding(0)
@@ -43,15 +52,21 @@ fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
     
     // Apply aspect ratio correction to vertex positions
     var pos = positions[in_vertex_index];
+
+    // Place the quad in its slot of the spread; a single image fills it
+    pos.x = mix(uniforms.quad_left, uniforms.quad_right, (pos.x + 1.0) * 0.5);
     
     // Adjust position based on aspect ratio
     if (uniforms.screen_aspect > uniforms.image_aspect) {
         // Screen is wider than the image, adjust x-coordinate
-        pos.x = pos.x * (uniforms.image_aspect / uniforms.screen_aspect) * uniforms.scale_factor;
+        pos.x = pos.x * (uniforms.image_aspect / uniforms.screen_aspect);
     } else {
         // Screen is taller than the image, adjust y-coordinate
-        pos.y = pos.y * (uniforms.screen_aspect / uniforms.image_aspect) * uniforms.scale_factor;
+        pos.y = pos.y * (uniforms.screen_aspect / uniforms.image_aspect);
     }
+
+    // Zoom about the centre of the window
+    pos = pos * uniforms.scale_factor;
     
     out.clip_position = vec4<f32>(pos, 0.0, 1.0);
     out.tex_coords = texcoords[in_vertex_index];
@@ -59,8 +74,37 @@ fn vs_main(@builtin(vertex_index) in_vertex_index: u32) -> VertexOutput {
     return out;
 }
 
+// Size in pixels of the squares of the transparency checkerboard
+const CHECKER_SIZE: f32 = 8.0;
+
+// Narkowicz's fit of the ACES filmic curve, mapping [0, inf) into [0, 1]
+fn tone_map_aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
 // Fragment shader
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    var color = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+
+    // Textures hold linear light (sRGB textures are decoded when sampled),
+    // and the surface encodes the result back to sRGB
+    var rgb = max(color.rgb, vec3<f32>(0.0)) * exp2(uniforms.exposure);
+    if (uniforms.tone_map > 0.5) {
+        rgb = tone_map_aces(rgb);
+    }
+    rgb = pow(rgb, vec3<f32>(1.0 / uniforms.gamma));
+    color = vec4<f32>(rgb, color.a);
+
+    // Show transparent areas over a light/dark grey checkerboard
+    let cell = floor(in.clip_position.xy / CHECKER_SIZE);
+    let checker = select(0.4, 0.6, (i32(cell.x) + i32(cell.y)) % 2 == 0);
+    let background = vec3<f32>(checker, checker, checker);
+
+    return vec4<f32>(mix(background, color.rgb, color.a), 1.0);
 }
\ No newline at end of file
@@ -0,0 +1,433 @@
+//! Ordering of the image list: by name, by file details, by EXIF date, or
+//! shuffled.
+
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use crate::formats;
+use crate::source::ImageSource;
+
+/// What the image list is sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortMode {
+    Natural,
+    Modified,
+    Size,
+    Date,
+    Dimensions,
+    Random,
+}
+
+impl SortMode {
+    /// Every mode, in the order the cycle-sort action steps through them.
+    pub const ALL: [SortMode; 6] = [
+        SortMode::Natural,
+        SortMode::Modified,
+        SortMode::Size,
+        SortMode::Date,
+        SortMode::Dimensions,
+        SortMode::Random,
+    ];
+
+    /// The name used in the config file and on the command line.
+    pub fn name(self) -> &'static str {
+        match self {
+            SortMode::Natural => "natural",
+            SortMode::Modified => "mtime",
+            SortMode::Size => "size",
+            SortMode::Date => "exif",
+            SortMode::Dimensions => "dimensions",
+            SortMode::Random => "random",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "natural" | "name" => Some(SortMode::Natural),
+            "mtime" | "modified" => Some(SortMode::Modified),
+            "size" => Some(SortMode::Size),
+            "exif" | "date" => Some(SortMode::Date),
+            "dimensions" | "resolution" => Some(SortMode::Dimensions),
+            "random" | "shuffle" => Some(SortMode::Random),
+            _ => None,
+        }
+    }
+
+    /// A description for notifications.
+    pub fn description(self) -> &'static str {
+        match self {
+            SortMode::Natural => "name",
+            SortMode::Modified => "modification time",
+            SortMode::Size => "file size",
+            SortMode::Date => "date taken",
+            SortMode::Dimensions => "dimensions",
+            SortMode::Random => "random order",
+        }
+    }
+
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|mode| *mode == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SortOrder {
+    pub mode: SortMode,
+    pub descending: bool,
+    /// Seeds the shuffle, so the same seed gives the same order
+    pub seed: u64,
+}
+
+impl SortOrder {
+    /// Sorts images, reading whatever the mode needs from each of them.
+    /// Images the mode has nothing to go on for (no EXIF date, a size that
+    /// can't be read) go last in either direction, and ties are broken by
+    /// name.
+    pub fn sort(&self, images: &mut Vec<ImageSource>) {
+        let mut keyed: Vec<(Option<u64>, PathBuf, ImageSource)> = std::mem::take(images)
+            .into_iter()
+            .map(|source| {
+                let path = sort_path(&source);
+                (self.key(&source, &path), path, source)
+            })
+            .collect();
+
+        keyed.sort_by(|(key_a, path_a, _), (key_b, path_b, _)| {
+            self.compare(*key_a, path_a, *key_b, path_b)
+        });
+
+        *images = keyed.into_iter().map(|(_, _, source)| source).collect();
+    }
+
+    /// Inserts an image into a list already in this order, only reading the
+    /// keys of the images the search passes through.
+    pub fn insert(&self, images: &mut Vec<ImageSource>, source: ImageSource) {
+        let path = sort_path(&source);
+        let key = self.key(&source, &path);
+        let index = images.partition_point(|other| {
+            let other_path = sort_path(other);
+            let other_key = self.key(other, &other_path);
+            self.compare(other_key, &other_path, key, &path).is_lt()
+        });
+        images.insert(index, source);
+    }
+
+    fn compare(
+        &self,
+        key_a: Option<u64>,
+        path_a: &Path,
+        key_b: Option<u64>,
+        path_b: &Path,
+    ) -> Ordering {
+        let ordering = match (key_a, key_b) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            (None, None) => Ordering::Equal,
+            (Some(_), None) => return Ordering::Less,
+            (None, Some(_)) => return Ordering::Greater,
+        }
+        .then_with(|| path_cmp(path_a, path_b));
+
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
+    fn key(&self, source: &ImageSource, path: &Path) -> Option<u64> {
+        match self.mode {
+            SortMode::Natural => None,
+            SortMode::Modified => {
+                let modified = source.path()?.metadata().ok()?.modified().ok()?;
+                Some(modified.duration_since(UNIX_EPOCH).ok()?.as_nanos() as u64)
+            }
+            SortMode::Size => match source.path() {
+                Some(path) => Some(path.metadata().ok()?.len()),
+                None => Some(source.read().ok()?.len() as u64),
+            },
+            SortMode::Date => exif_date(source),
+            SortMode::Dimensions => {
+                let (width, height) = formats::image_dimensions(source)?;
+                Some(width as u64 * height as u64)
+            }
+            SortMode::Random => Some(shuffle_key(self.seed, path)),
+        }
+    }
+}
+
+/// The path an image sorts by by name; archive entries sort as if the
+/// archive were a folder.
+fn sort_path(source: &ImageSource) -> PathBuf {
+    match source {
+        ImageSource::File(path) => path.clone(),
+        ImageSource::ArchiveEntry { archive, entry } => archive.join(entry),
+        ImageSource::Memory(image) => PathBuf::from(&image.name),
+    }
+}
+
+/// Reads EXIF DateTimeOriginal as a number that sorts chronologically
+/// (`YYYYMMDDhhmmss`).
+fn exif_date(source: &ImageSource) -> Option<u64> {
+    let date = exif_datetime(source)?;
+    Some(
+        [
+            date.year as u64,
+            date.month as u64,
+            date.day as u64,
+            date.hour as u64,
+            date.minute as u64,
+            date.second as u64,
+        ]
+        .into_iter()
+        .fold(0, |number, part| number * 100 + part),
+    )
+}
+
+/// Reads EXIF DateTimeOriginal, when the photo was taken.
+pub fn exif_datetime(source: &ImageSource) -> Option<exif::DateTime> {
+    let reader = exif::Reader::new();
+    let exif = match source.path() {
+        Some(path) => reader
+            .read_from_container(&mut BufReader::new(File::open(path).ok()?))
+            .ok()?,
+        None => reader
+            .read_from_container(&mut Cursor::new(source.read().ok()?))
+            .ok()?,
+    };
+
+    let field = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)?;
+    let exif::Value::Ascii(values) = &field.value else {
+        return None;
+    };
+    exif::DateTime::from_ascii(values.first()?).ok()
+}
+
+/// A pseudo-random sort key: the path hashed with FNV-1a and mixed with
+/// the seed by SplitMix64's finalizer.
+fn shuffle_key(seed: u64, path: &Path) -> u64 {
+    let hash = path
+        .as_os_str()
+        .as_encoded_bytes()
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        });
+
+    let mut z = hash ^ seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Compares names the way people read them: runs of digits compare by
+/// value, so `page2` comes before `page10`, and letters ignore case. Names
+/// that only differ in case or leading zeros fall back to a plain comparison
+/// so the order is still total.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut left = chunks(a);
+    let mut right = chunks(b);
+
+    loop {
+        let ordering = match (left.next(), right.next()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some((true, x)), Some((true, y))) => {
+                let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+            }
+            (Some((_, x)), Some((_, y))) => x.to_lowercase().cmp(&y.to_lowercase()),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+}
+
+/// Orders paths folder by folder, so the images of each folder stay
+/// together: a folder's own images come before its sub-folders, and names
+/// at each level compare naturally.
+pub fn path_cmp(a: &Path, b: &Path) -> Ordering {
+    let folders = |path: &Path| {
+        path.parent()
+            .into_iter()
+            .flat_map(Path::components)
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+    };
+    let name = |path: &Path| {
+        path.file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    };
+
+    let (folders_a, folders_b) = (folders(a), folders(b));
+    folders_a
+        .iter()
+        .zip(&folders_b)
+        .map(|(x, y)| natural_cmp(x, y))
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or_else(|| folders_a.len().cmp(&folders_b.len()))
+        .then_with(|| natural_cmp(&name(a), &name(b)))
+}
+
+/// Splits a name into alternating runs of digits and non-digits, flagging
+/// the digit runs.
+fn chunks(name: &str) -> impl Iterator<Item = (bool, &str)> {
+    let mut rest = name;
+    std::iter::from_fn(move || {
+        let first = rest.chars().next()?;
+        let digits = first.is_ascii_digit();
+        let end = rest
+            .find(|c: char| c.is_ascii_digit() != digits)
+            .unwrap_or(rest.len());
+        let (chunk, tail) = rest.split_at(end);
+        rest = tail;
+        Some((digits, chunk))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names: Vec<String> = names.iter().map(|name| name.to_string()).collect();
+        names.sort_by(|a, b| natural_cmp(a, b));
+        names
+    }
+
+    #[test]
+    fn numbers_compare_by_value() {
+        assert_eq!(
+            sorted(&["page10", "page2", "page1", "page02"]),
+            ["page1", "page02", "page2", "page10"]
+        );
+    }
+
+    #[test]
+    fn leading_zeros_only_break_ties() {
+        assert_eq!(natural_cmp("007", "7"), "007".cmp("7"));
+        assert_eq!(natural_cmp("007", "8"), Ordering::Less);
+        assert_eq!(natural_cmp("0", "00"), Ordering::Less);
+    }
+
+    #[test]
+    fn long_digit_runs() {
+        // Longer than a u64 holds
+        let big = "123456789012345678901234567890";
+        let bigger = "123456789012345678901234567891";
+        assert_eq!(natural_cmp(big, bigger), Ordering::Less);
+        assert_eq!(natural_cmp(&format!("a{big}"), "a99"), Ordering::Greater);
+        assert_eq!(
+            natural_cmp(&format!("a0{big}"), &format!("a{big}")),
+            Ordering::Less
+        );
+    }
+
+    #[test]
+    fn case_is_ignored_until_a_tie() {
+        assert_eq!(sorted(&["b", "A", "a", "B"]), ["A", "a", "B", "b"]);
+        assert_eq!(natural_cmp("Photo", "photo"), Ordering::Less);
+        assert_eq!(natural_cmp("Zebra", "apple"), Ordering::Greater);
+    }
+
+    #[test]
+    fn folders_come_before_sub_folders() {
+        let mut paths: Vec<PathBuf> = [
+            "/pics/sub/a.png",
+            "/pics/z.png",
+            "/pics/a10.png",
+            "/pics/a9.png",
+            "/pics/Sub2/a.png",
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+        paths.sort_by(|a, b| path_cmp(a, b));
+        assert_eq!(
+            paths,
+            [
+                "/pics/a9.png",
+                "/pics/a10.png",
+                "/pics/z.png",
+                "/pics/sub/a.png",
+                "/pics/Sub2/a.png",
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn images_without_a_key_go_last() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = |name: &str, size: usize| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, vec![0; size]).unwrap();
+            ImageSource::File(path)
+        };
+        let small = file("small.png", 1);
+        let large = file("large.png", 100);
+        // Gone, so its size can't be read
+        let missing = ImageSource::File(dir.path().join("a-missing.png"));
+
+        let mut order = SortOrder {
+            mode: SortMode::Size,
+            descending: false,
+            seed: 0,
+        };
+        let mut images = vec![missing.clone(), large.clone(), small.clone()];
+        order.sort(&mut images);
+        assert_eq!(images, [small.clone(), large.clone(), missing.clone()]);
+
+        order.descending = true;
+        order.sort(&mut images);
+        assert_eq!(images, [large, small, missing]);
+    }
+
+    #[test]
+    fn insert_keeps_the_order() {
+        let order = SortOrder {
+            mode: SortMode::Random,
+            descending: false,
+            seed: 42,
+        };
+        let mut images: Vec<ImageSource> = (0..20)
+            .map(|i| ImageSource::File(PathBuf::from(format!("/a/{i}.png"))))
+            .collect();
+        order.sort(&mut images);
+        let mut expected = images.clone();
+
+        let mut inserted = images.split_off(10);
+        inserted.reverse();
+        for source in inserted {
+            order.insert(&mut images, source);
+        }
+        order.sort(&mut expected);
+        assert_eq!(images, expected);
+    }
+
+    #[test]
+    fn natural_order_descending() {
+        let order = SortOrder {
+            mode: SortMode::Natural,
+            descending: true,
+            seed: 0,
+        };
+        let mut images: Vec<ImageSource> = ["/a/img2.png", "/a/img10.png", "/a/img1.png"]
+            .iter()
+            .map(|path| ImageSource::File(PathBuf::from(path)))
+            .collect();
+        order.sort(&mut images);
+        let paths: Vec<_> = images.iter().filter_map(ImageSource::path).collect();
+        assert_eq!(
+            paths,
+            ["/a/img10.png", "/a/img2.png", "/a/img1.png"].map(Path::new)
+        );
+    }
+}
@@ -0,0 +1,386 @@
+//! Where images come from: plain files, entries inside a ZIP/CBZ or TAR/CBT
+//! archive, which is browsed like a directory without extracting it, or
+//! image data held in memory until it is saved.
+
+use anyhow::{anyhow, Context, Result};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::SystemTime;
+
+/// How many leading bytes of each archive entry are read for format
+/// detection.
+const HEADER_LEN: u64 = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ImageSource {
+    File(PathBuf),
+    ArchiveEntry { archive: PathBuf, entry: String },
+    Memory(MemoryImage),
+}
+
+/// Image data that isn't in a file, such as a screenshot piped to stdin or
+/// pasted from the clipboard. Two are only equal if they are the same item,
+/// so the data never has to be compared or hashed.
+#[derive(Debug, Clone)]
+pub struct MemoryImage {
+    id: u64,
+    /// A file name to offer when saving it
+    pub name: String,
+    pub data: Arc<[u8]>,
+}
+
+impl MemoryImage {
+    pub fn new(name: String, data: Vec<u8>) -> Self {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            name,
+            data: data.into(),
+        }
+    }
+}
+
+impl PartialEq for MemoryImage {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for MemoryImage {}
+
+impl std::hash::Hash for MemoryImage {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl ImageSource {
+    /// The file on disk, for operations that only make sense on real files
+    /// (deleting, setting the wallpaper, ...).
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            ImageSource::File(path) => Some(path),
+            ImageSource::ArchiveEntry { .. } | ImageSource::Memory(_) => None,
+        }
+    }
+
+    /// A short name for the window title: the file name, the archive's name
+    /// and the entry within it, or the name an unsaved image would be saved
+    /// under.
+    pub fn name(&self) -> String {
+        match self {
+            ImageSource::File(path) => path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into(),
+            ImageSource::ArchiveEntry { archive, entry } => format!(
+                "{}/{}",
+                archive.file_name().unwrap_or_default().to_string_lossy(),
+                entry
+            ),
+            ImageSource::Memory(image) => image.name.clone(),
+        }
+    }
+
+    /// The folder the image is in, for jumping between folders. Archive
+    /// entries are grouped by their folder inside the archive.
+    pub fn folder(&self) -> PathBuf {
+        match self {
+            ImageSource::File(path) => path.parent().unwrap_or(Path::new("")).to_path_buf(),
+            ImageSource::ArchiveEntry { archive, entry } => {
+                archive.join(Path::new(entry).parent().unwrap_or(Path::new("")))
+            }
+            ImageSource::Memory(_) => PathBuf::new(),
+        }
+    }
+
+    /// The full location, for messages.
+    pub fn display(&self) -> String {
+        match self {
+            ImageSource::File(path) => path.display().to_string(),
+            ImageSource::ArchiveEntry { archive, entry } => {
+                format!("{} in {}", entry, archive.display())
+            }
+            ImageSource::Memory(image) => format!("{} (not saved)", image.name),
+        }
+    }
+
+    /// The lowercased file extension, used to identify formats that have no
+    /// signature.
+    pub fn extension(&self) -> Option<String> {
+        let name = match self {
+            ImageSource::File(path) => path.file_name()?.to_str()?,
+            ImageSource::ArchiveEntry { entry, .. } => entry.rsplit('/').next()?,
+            // Named after the format sniffed from the data, so nothing to add
+            ImageSource::Memory(_) => return None,
+        };
+        let (stem, extension) = name.rsplit_once('.')?;
+        (!stem.is_empty()).then(|| extension.to_ascii_lowercase())
+    }
+
+    /// Reads the whole image into memory.
+    pub fn read(&self) -> Result<Vec<u8>> {
+        match self {
+            ImageSource::File(path) => {
+                std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))
+            }
+            ImageSource::ArchiveEntry { archive, entry } => read_entry(archive, entry)
+                .with_context(|| format!("Failed to read {} from {}", entry, archive.display())),
+            ImageSource::Memory(image) => Ok(image.data.to_vec()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+}
+
+/// Identifies a ZIP-based (`.zip`, `.cbz`) or TAR-based (`.tar`, `.cbt`)
+/// archive by its signature. Documents such as `.docx`, `.epub`, `.odt` and
+/// `.kra` are ZIPs too, so a ZIP also needs one of the archive extensions.
+fn archive_kind(path: &Path) -> Option<ArchiveKind> {
+    let mut header = Vec::new();
+    File::open(path)
+        .ok()?
+        .take(512)
+        .read_to_end(&mut header)
+        .ok()?;
+
+    let zip_extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip") || ext.eq_ignore_ascii_case("cbz"));
+    if header.starts_with(b"PK\x03\x04") || header.starts_with(b"PK\x05\x06") {
+        zip_extension.then_some(ArchiveKind::Zip)
+    } else if header.get(257..262) == Some(b"ustar") {
+        Some(ArchiveKind::Tar)
+    } else {
+        None
+    }
+}
+
+pub fn is_archive(path: &Path) -> bool {
+    path.is_file() && archive_kind(path).is_some()
+}
+
+/// Skips directories and the metadata files macOS leaves in archives.
+fn is_listed(entry: &str) -> bool {
+    !entry.ends_with('/')
+        && !entry.split('/').any(|part| part == "__MACOSX")
+        && !entry.rsplit('/').next().unwrap_or(entry).starts_with("._")
+}
+
+/// Lists the files in an archive, with the leading bytes of each for
+/// format detection.
+pub fn list_archive(path: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    let file = BufReader::new(File::open(path)?);
+    let mut entries = Vec::new();
+
+    match archive_kind(path) {
+        Some(ArchiveKind::Zip) => {
+            let mut zip = zip::ZipArchive::new(file).context("Failed to read ZIP archive")?;
+            for i in 0..zip.len() {
+                let entry = zip.by_index(i)?;
+                if !entry.is_file() || !is_listed(entry.name()) {
+                    continue;
+                }
+                let name = entry.name().to_string();
+                let mut header = Vec::new();
+                entry.take(HEADER_LEN).read_to_end(&mut header)?;
+                entries.push((name, header));
+            }
+        }
+        Some(ArchiveKind::Tar) => {
+            let modified = path.metadata()?.modified()?;
+            let mut index = TarIndex::new();
+            let mut tar = tar::Archive::new(file);
+            for entry in tar.entries().context("Failed to read TAR archive")? {
+                let entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let name = entry.path()?.to_string_lossy().into_owned();
+                if !is_listed(&name) {
+                    continue;
+                }
+                index.insert(name.clone(), (entry.raw_file_position(), entry.size()));
+                let mut header = Vec::new();
+                entry.take(HEADER_LEN).read_to_end(&mut header)?;
+                entries.push((name, header));
+            }
+            TAR_INDEXES
+                .lock()
+                .unwrap()
+                .insert(path.to_path_buf(), (modified, Arc::new(index)));
+        }
+        None => return Err(anyhow!("Not a supported archive: {}", path.display())),
+    }
+
+    Ok(entries)
+}
+
+/// Where each file's data starts in a TAR and how long it is. TAR has no
+/// index of its own, and scanning the whole archive for every page read
+/// would make sorting a large one quadratic.
+type TarIndex = HashMap<String, (u64, u64)>;
+
+/// An index along with the archive's modification time when it was built,
+/// so a changed archive is indexed afresh.
+type TimedIndex = (SystemTime, Arc<TarIndex>);
+
+/// The indexes built when archives were listed or first read from.
+static TAR_INDEXES: LazyLock<Mutex<HashMap<PathBuf, TimedIndex>>> = LazyLock::new(Default::default);
+
+/// The index of a TAR, building it if the archive hasn't been listed or has
+/// changed since.
+fn tar_index(path: &Path) -> Result<Arc<TarIndex>> {
+    let modified = path.metadata()?.modified()?;
+    if let Some((built, index)) = TAR_INDEXES.lock().unwrap().get(path) {
+        if *built == modified {
+            return Ok(index.clone());
+        }
+    }
+
+    let mut index = TarIndex::new();
+    let mut tar = tar::Archive::new(BufReader::new(File::open(path)?));
+    for entry in tar.entries()? {
+        let entry = entry?;
+        if entry.header().entry_type().is_file() {
+            let name = entry.path()?.to_string_lossy().into_owned();
+            index.insert(name, (entry.raw_file_position(), entry.size()));
+        }
+    }
+    let index = Arc::new(index);
+    TAR_INDEXES
+        .lock()
+        .unwrap()
+        .insert(path.to_path_buf(), (modified, index.clone()));
+    Ok(index)
+}
+
+fn read_entry(archive: &Path, name: &str) -> Result<Vec<u8>> {
+    let file = BufReader::new(File::open(archive)?);
+    let mut data = Vec::new();
+
+    match archive_kind(archive) {
+        Some(ArchiveKind::Zip) => {
+            zip::ZipArchive::new(file)?
+                .by_name(name)?
+                .read_to_end(&mut data)?;
+        }
+        Some(ArchiveKind::Tar) => {
+            let &(offset, size) = tar_index(archive)?
+                .get(name)
+                .ok_or_else(|| anyhow!("No such entry"))?;
+            let mut file = file;
+            file.seek(SeekFrom::Start(offset))?;
+            file.take(size).read_to_end(&mut data)?;
+        }
+        None => return Err(anyhow!("Not a supported archive")),
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn write_zip(path: &Path, files: &[(&str, &[u8])]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for (name, data) in files {
+            zip.start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(data).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn write_tar(path: &Path, files: &[(&str, &[u8])]) {
+        let mut tar = tar::Builder::new(File::create(path).unwrap());
+        for (name, data) in files {
+            let mut header = tar::Header::new_ustar();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append_data(&mut header, name, *data).unwrap();
+        }
+        tar.finish().unwrap();
+    }
+
+    #[test]
+    fn zip_needs_an_archive_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        let files: &[(&str, &[u8])] = &[("page1.png", b"\x89PNG")];
+        for name in [
+            "comic.cbz",
+            "photos.ZIP",
+            "report.docx",
+            "book.epub",
+            "painting.kra",
+        ] {
+            write_zip(&dir.path().join(name), files);
+        }
+        assert!(is_archive(&dir.path().join("comic.cbz")));
+        assert!(is_archive(&dir.path().join("photos.ZIP")));
+        assert!(!is_archive(&dir.path().join("report.docx")));
+        assert!(!is_archive(&dir.path().join("book.epub")));
+        assert!(!is_archive(&dir.path().join("painting.kra")));
+    }
+
+    #[test]
+    fn tar_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("comic.cbt");
+        let big = vec![7; 5000];
+        write_tar(
+            &path,
+            &[
+                ("01.png", b"first"),
+                ("sub/02.png", &big),
+                ("._03.png", b"resource fork"),
+                ("03.png", b"third"),
+            ],
+        );
+        assert!(is_archive(&path));
+
+        let names: Vec<String> = list_archive(&path)
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, ["01.png", "sub/02.png", "03.png"]);
+
+        let read = |entry: &str| {
+            ImageSource::ArchiveEntry {
+                archive: path.clone(),
+                entry: entry.to_string(),
+            }
+            .read()
+        };
+        assert_eq!(read("03.png").unwrap(), b"third");
+        assert_eq!(read("sub/02.png").unwrap(), big);
+        assert_eq!(read("01.png").unwrap(), b"first");
+        assert!(read("04.png").is_err());
+    }
+
+    #[test]
+    fn tar_read_without_listing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pages.tar");
+        write_tar(&path, &[("a.png", b"a"), ("b.png", b"bb")]);
+        let source = ImageSource::ArchiveEntry {
+            archive: path,
+            entry: "b.png".to_string(),
+        };
+        assert_eq!(source.read().unwrap(), b"bb");
+    }
+}
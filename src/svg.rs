@@ -0,0 +1,65 @@
+//! SVG rendering with resvg. Vectors have no fixed size, so they are
+//! rasterized to whatever size they are displayed at.
+
+use anyhow::{anyhow, Context, Result};
+use image::{DynamicImage, RgbaImage};
+use resvg::{tiny_skia, usvg};
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+
+/// System fonts for `<text>` elements, loaded once on first use.
+fn fontdb() -> Arc<usvg::fontdb::Database> {
+    static FONTDB: OnceLock<Arc<usvg::fontdb::Database>> = OnceLock::new();
+    FONTDB
+        .get_or_init(|| {
+            let mut fontdb = usvg::fontdb::Database::new();
+            fontdb.load_system_fonts();
+            Arc::new(fontdb)
+        })
+        .clone()
+}
+
+/// Renders an SVG (or gzipped SVGZ) scaled to fit within `fit`, or at its
+/// own size when no size is given. Relative references are resolved against
+/// `resources_dir`.
+pub fn decode(
+    data: &[u8],
+    resources_dir: Option<&Path>,
+    fit: Option<(u32, u32)>,
+) -> Result<DynamicImage> {
+    let options = usvg::Options {
+        resources_dir: resources_dir.map(Path::to_path_buf),
+        fontdb: fontdb(),
+        ..Default::default()
+    };
+    let tree = usvg::Tree::from_data(data, &options).context("Failed to parse SVG")?;
+
+    let size = tree.size();
+    let scale = match fit {
+        Some((width, height)) => (width as f32 / size.width()).min(height as f32 / size.height()),
+        None => 1.0,
+    };
+    let width = (size.width() * scale).round().max(1.0) as u32;
+    let height = (size.height() * scale).round().max(1.0) as u32;
+
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)
+        .ok_or_else(|| anyhow!("SVG is too large to render ({}x{})", width, height))?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    // tiny-skia works in premultiplied alpha
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+    let buffer = RgbaImage::from_raw(width, height, pixels)
+        .ok_or_else(|| anyhow!("SVG render has the wrong size"))?;
+    Ok(DynamicImage::ImageRgba8(buffer))
+}
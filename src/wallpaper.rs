@@ -0,0 +1,96 @@
+//! Wallpaper rotation: a small shell loop that cycles hyprpaper through a
+//! set of images, and keeps going after EleViewr exits.
+
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use crate::ipc;
+
+/// Passed as the loop's `$0`, so a running rotation can be recognised by its
+/// command line.
+const PROCESS_NAME: &str = "eleviewr-wallpaper-rotation";
+
+const SCRIPT: &str = r#"interval=$1
+shift
+while :; do
+    for image do
+        sleep "$interval"
+        hyprctl hyprpaper preload "$image" >/dev/null
+        hyprctl hyprpaper wallpaper ",$image" >/dev/null
+        hyprctl hyprpaper unload unused >/dev/null
+    done
+done"#;
+
+/// Where the running rotation's process ID is kept: in the same private
+/// folder as the control socket, as anyone who could write it could have
+/// `stop_rotation` kill a process of their choosing.
+fn pid_file() -> Result<PathBuf> {
+    let dir = ipc::runtime_dir()
+        .ok_or_else(|| anyhow!("$XDG_RUNTIME_DIR is not set, so the wallpaper can't rotate"))?;
+    ipc::private_dir(&dir)?;
+    Ok(dir.join("wallpaper.pid"))
+}
+
+/// Cycles the wallpaper through `images`, changing it every `interval`
+/// seconds and replacing any earlier rotation. The first image should
+/// already be set, so the rotation starts with the second one and comes
+/// back round to it.
+pub fn start_rotation(images: &[PathBuf], interval: u64) -> Result<()> {
+    let pid_file = pid_file()?;
+    stop_rotation();
+
+    let Some((first, rest)) = images.split_first() else {
+        return Ok(());
+    };
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(SCRIPT)
+        .arg(PROCESS_NAME)
+        .arg(interval.to_string())
+        .args(rest)
+        .arg(first)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        // Its own process group, so it survives the terminal closing and
+        // can be stopped together with its `sleep`
+        .process_group(0)
+        .spawn()
+        .context("Failed to start wallpaper rotation")?;
+
+    if let Err(e) = fs::write(pid_file, child.id().to_string()) {
+        let _ = child.kill();
+        return Err(e).context("Failed to record wallpaper rotation");
+    }
+    Ok(())
+}
+
+/// Stops the rotation started by this or an earlier run, if it is still
+/// going.
+pub fn stop_rotation() {
+    let Ok(path) = pid_file() else {
+        return;
+    };
+    let Some(pid) = fs::read_to_string(&path)
+        .ok()
+        .and_then(|pid| pid.trim().parse::<u32>().ok())
+    else {
+        return;
+    };
+
+    // The process ID may have been reused since, so check it is still ours
+    let ours = fs::read(format!("/proc/{}/cmdline", pid)).is_ok_and(|cmdline| {
+        cmdline
+            .split(|byte| *byte == 0)
+            .any(|arg| arg == PROCESS_NAME.as_bytes())
+    });
+    if ours {
+        let _ = Command::new("kill")
+            .args(["-TERM", "--", &format!("-{}", pid)])
+            .status();
+    }
+    let _ = fs::remove_file(path);
+}